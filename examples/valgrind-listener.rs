@@ -0,0 +1,87 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Listens for processes run with `--log-socket=127.0.0.1:<port>` and writes the output of each
+//! process to `<dir>/valgrind.<pid>.log`.
+//!
+//! Usage: `valgrind-listener [<port> [<dir>]]`
+
+extern crate valgrind;
+
+use std::collections::{HashMap};
+use std::io::{File};
+use std::os;
+
+use valgrind::log_socket::{ClosedEvent, DEFAULT_PORT, LineEvent, LogSocketListener};
+
+fn main() {
+    let args = os::args();
+    let port = match args.as_slice().get(1) {
+            None => DEFAULT_PORT,
+            Some(port) => match from_str(port.as_slice()) {
+                None => fail!("invalid port '{}'", port),
+                Some(port) => port,
+            },
+        };
+    let dir = Path::new(args.as_slice().get(2).map(|dir| dir.as_slice()).unwrap_or("."));
+
+    let listener = match LogSocketListener::bind("127.0.0.1", port) {
+            Err(e) => fail!("failed to listen on port {}: {}", port, e),
+            Ok(listener) => listener,
+        };
+    println!("listening on 127.0.0.1:{}", port);
+
+    let mut files: HashMap<uint, File> = HashMap::new();
+    let rx = listener.spawn();
+    loop {
+        let event = match rx.recv_opt() {
+                Err(()) => break,
+                Ok(event) => event,
+            };
+        match event.kind {
+            LineEvent(line) => {
+                if !files.contains_key(&event.connection) {
+                    // The first line of the output carries the PID.
+                    let name = match event.opt_pid {
+                            Some(pid) => format!("valgrind.{}.log", pid),
+                            None => format!("valgrind.connection-{}.log", event.connection),
+                        };
+                    let path = dir.join(name);
+                    match File::create(&path) {
+                        Err(e) => fail!("failed to create {}: {}", path.display(), e),
+                        Ok(file) => {
+                            println!("({}) writing {}", event.connection, path.display());
+                            files.insert(event.connection, file);
+                        },
+                    }
+                }
+                match files.find_mut(&event.connection).unwrap().write_str(line.as_slice()) {
+                    Err(e) => fail!("failed to write the log of connection {}: {}", event.connection, e),
+                    Ok(()) => (),
+                }
+            },
+            ClosedEvent(opt_error, reports) => {
+                files.remove(&event.connection);
+                match opt_error {
+                    Some(e) => println!("({}) connection failed: {}", event.connection, e),
+                    None => (),
+                }
+                for report in reports.iter() {
+                    println!("({}) process {} exited with {} error(s)",
+                             event.connection,
+                             report.opt_pid.map(|pid| pid.to_string()).unwrap_or(String::from_str("?")),
+                             report.errors.len());
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Annotations of custom allocators, so that Memcheck tracks their blocks like those of `malloc`.
+//! All of them do nothing when not run on Valgrind.
+//!
+//! # See also
+//! * [Memory Pools: describing and working with custom allocators](http://valgrind.org/docs/manual/mc-manual.html#mc-manual.mempools). Valgrind User Manual.
+
+use std::cmp;
+use std::num::{CheckedAdd, CheckedMul};
+use std::option::{Option};
+use std::ptr;
+use std::rt::heap;
+
+use client_request::{client_request};
+use memcheck::{make_mem_noaccess_ptr, make_mem_undefined_ptr};
+
+static MALLOCLIKE_BLOCK: uint = 0x1301;
+static FREELIKE_BLOCK: uint = 0x1302;
+static CREATE_MEMPOOL: uint = 0x1303;
+static DESTROY_MEMPOOL: uint = 0x1304;
+static MEMPOOL_ALLOC: uint = 0x1305;
+static MEMPOOL_FREE: uint = 0x1306;
+static MEMPOOL_TRIM: uint = 0x1307;
+static MEMPOOL_CHANGE: uint = 0x1309;
+static RESIZEINPLACE_BLOCK: uint = 0x130b;
+
+/// Announces that the `size` bytes at `addr` were allocated like with `malloc`, with `redzone`
+/// bytes of padding on each side. If `is_zeroed`, the block is defined; otherwise it is undefined.
+pub unsafe fn malloclike_block(addr: *const u8, size: uint, redzone: uint, is_zeroed: bool) {
+    client_request(MALLOCLIKE_BLOCK, &[addr as uint, size, redzone, is_zeroed as uint]);
+}
+
+/// Announces that the block at `addr`, announced with `malloclike_block()`, was freed.
+pub unsafe fn freelike_block(addr: *const u8, redzone: uint) {
+    client_request(FREELIKE_BLOCK, &[addr as uint, redzone]);
+}
+
+/// Announces that the block at `addr`, announced with `malloclike_block()`, was resized in place
+/// from `old_size` to `new_size` bytes.
+pub unsafe fn resizeinplace_block(addr: *const u8, old_size: uint, new_size: uint, redzone: uint) {
+    client_request(RESIZEINPLACE_BLOCK, &[addr as uint, old_size, new_size, redzone]);
+}
+
+/// Announces that `pool` is the anchor of a memory pool, whose chunks have `redzone` bytes of
+/// padding on each side. If `is_zeroed`, chunks are defined when allocated.
+pub unsafe fn create_mempool(pool: *const u8, redzone: uint, is_zeroed: bool) {
+    client_request(CREATE_MEMPOOL, &[pool as uint, redzone, is_zeroed as uint]);
+}
+
+/// Announces that the memory pool anchored at `pool` was destroyed, freeing all of its chunks.
+pub unsafe fn destroy_mempool(pool: *const u8) {
+    client_request(DESTROY_MEMPOOL, &[pool as uint]);
+}
+
+/// Announces that the chunk of `size` bytes at `addr` was allocated from the pool `pool`.
+pub unsafe fn mempool_alloc(pool: *const u8, addr: *const u8, size: uint) {
+    client_request(MEMPOOL_ALLOC, &[pool as uint, addr as uint, size]);
+}
+
+/// Announces that the chunk at `addr` was freed to the pool `pool`.
+pub unsafe fn mempool_free(pool: *const u8, addr: *const u8) {
+    client_request(MEMPOOL_FREE, &[pool as uint, addr as uint]);
+}
+
+/// Announces that the chunks of the pool `pool` outside of the `size` bytes at `addr` were freed.
+pub unsafe fn mempool_trim(pool: *const u8, addr: *const u8, size: uint) {
+    client_request(MEMPOOL_TRIM, &[pool as uint, addr as uint, size]);
+}
+
+/// Announces that the chunk at `old_addr` of the pool `pool` was moved to `new_addr` and resized
+/// to `size` bytes.
+pub unsafe fn mempool_change(pool: *const u8, old_addr: *const u8, new_addr: *const u8, size: uint) {
+    client_request(MEMPOOL_CHANGE, &[pool as uint, old_addr as uint, new_addr as uint, size]);
+}
+
+/// A memory pool, which is destroyed when dropped. The pool is identified by its anchor, which is
+/// usually the address of the allocator object.
+pub struct Mempool {
+    anchor: *const u8,
+}
+
+impl Mempool {
+
+    /// Announces a memory pool anchored at `anchor`.
+    pub unsafe fn new(anchor: *const u8, redzone: uint, is_zeroed: bool) -> Mempool {
+        create_mempool(anchor, redzone, is_zeroed);
+        Mempool {
+            anchor: anchor,
+        }
+    }
+
+    /// Announces the allocation of the chunk of `size` bytes at `addr`.
+    pub unsafe fn alloc(&self, addr: *const u8, size: uint) {
+        mempool_alloc(self.anchor, addr, size);
+    }
+
+    /// Announces the freeing of the chunk at `addr`.
+    pub unsafe fn free(&self, addr: *const u8) {
+        mempool_free(self.anchor, addr);
+    }
+
+    /// Announces the freeing of the chunks outside of the `size` bytes at `addr`.
+    pub unsafe fn trim(&self, addr: *const u8, size: uint) {
+        mempool_trim(self.anchor, addr, size);
+    }
+
+    /// Announces the move of the chunk at `old_addr` to `new_addr`, with the new size `size`.
+    pub unsafe fn change(&self, old_addr: *const u8, new_addr: *const u8, size: uint) {
+        mempool_change(self.anchor, old_addr, new_addr, size);
+    }
+}
+
+impl Drop for Mempool {
+    fn drop(&mut self) {
+        unsafe { destroy_mempool(self.anchor); }
+    }
+}
+
+/// An allocator of raw memory, with the interface of `std::rt::heap`.
+///
+/// This is not a replacement for the process allocator: `Box`, `Vec` and the rest of the standard
+/// library always allocate with `std::rt::heap`, and an `Allocator` cannot be installed in its
+/// place. Only code that allocates through an `Allocator` explicitly, such as a pool or arena,
+/// uses it.
+pub trait Allocator {
+    /// Returns a block of `size` bytes aligned to `align`, or null if out of memory.
+    unsafe fn allocate(&self, size: uint, align: uint) -> *mut u8;
+
+    /// Frees the block at `ptr` of `size` bytes, allocated with the alignment `align`.
+    unsafe fn deallocate(&self, ptr: *mut u8, size: uint, align: uint);
+
+    /// Resizes the block at `ptr` from `old_size` to `size` bytes, returning the address of the
+    /// resized block, or null if out of memory.
+    unsafe fn reallocate(&self, ptr: *mut u8, size: uint, align: uint, old_size: uint) -> *mut u8;
+}
+
+/// The allocator of `std::rt::heap`.
+pub struct HeapAllocator;
+
+impl Allocator for HeapAllocator {
+    unsafe fn allocate(&self, size: uint, align: uint) -> *mut u8 {
+        heap::allocate(size, align)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, size: uint, align: uint) {
+        heap::deallocate(ptr, size, align)
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, size: uint, align: uint, old_size: uint) -> *mut u8 {
+        heap::reallocate(ptr, size, align, old_size)
+    }
+}
+
+/// An allocator that announces the blocks of an inner allocator with `malloclike_block()` and
+/// `freelike_block()`, so that Memcheck reports leaks, use after free and (with a redzone)
+/// overruns of them.
+///
+/// The inner allocator must not be `malloc` itself, which Memcheck already tracks.
+///
+/// Freed blocks are unaddressable until allocated again. The inner allocator may write to the
+/// block that it is given in `deallocate`, e.g. to link it into a free list, as the block is
+/// addressable for the duration of that call. Other accesses to freed blocks, such as reading the
+/// link of the head of a free list in `allocate`, must be wrapped by the inner allocator itself:
+///
+/// ```ignore
+/// make_mem_defined_ptr(head as *const u8, mem::size_of::<*mut u8>());
+/// let next = *(head as *mut *mut u8);
+/// make_mem_noaccess_ptr(head as *const u8, mem::size_of::<*mut u8>());
+/// ```
+pub struct Annotated<A> {
+    inner: A,
+    redzone: uint,
+}
+
+impl<A: Allocator> Annotated<A> {
+
+    /// Wraps `inner`, without redzones.
+    pub fn new(inner: A) -> Annotated<A> {
+        Annotated::with_redzone(inner, 0)
+    }
+
+    /// Wraps `inner`, padding each block with at least `redzone` unaddressable bytes on each
+    /// side. The padding is rounded up to the alignment of the block.
+    pub fn with_redzone(inner: A, redzone: uint) -> Annotated<A> {
+        Annotated {
+            inner: inner,
+            redzone: redzone,
+        }
+    }
+
+    /// The inner allocator.
+    pub fn inner<'a>(&'a self) -> &'a A {
+        &self.inner
+    }
+
+    /// The padding on each side of a block with the alignment `align`, and the size of a block of
+    /// `size` bytes with that padding, or `None` if these overflow.
+    fn padded_size(&self, size: uint, align: uint) -> Option<(uint, uint)> {
+        let align = cmp::max(align, 1);
+        let opt_padding = self.redzone.checked_add(&(align - 1)).map(|n| n / align * align);
+        opt_padding.and_then(|padding| {
+            padding.checked_mul(&2)
+                .and_then(|both| size.checked_add(&both))
+                .map(|padded_size| (padding, padded_size))
+        })
+    }
+}
+
+impl<A: Allocator> Allocator for Annotated<A> {
+    unsafe fn allocate(&self, size: uint, align: uint) -> *mut u8 {
+        let (padding, padded_size) = match self.padded_size(size, align) {
+                None => return ptr::mut_null(),
+                Some(sizes) => sizes,
+            };
+        let base = self.inner.allocate(padded_size, align);
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.offset(padding as int);
+        if padding > 0 {
+            make_mem_noaccess_ptr(base as *const u8, padding);
+            make_mem_noaccess_ptr(ptr.offset(size as int) as *const u8, padding);
+        }
+        malloclike_block(ptr as *const u8, size, padding, false);
+        ptr
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, size: uint, align: uint) {
+        // The block was allocated, so its padded size did not overflow.
+        let (padding, padded_size) = self.padded_size(size, align).unwrap();
+        freelike_block(ptr as *const u8, padding);
+        // Let the inner allocator write its bookkeeping into the freed block.
+        let base = ptr.offset(-(padding as int));
+        make_mem_undefined_ptr(base as *const u8, padded_size);
+        self.inner.deallocate(base, padded_size, align);
+        make_mem_noaccess_ptr(base as *const u8, padded_size);
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, size: uint, align: uint, old_size: uint) -> *mut u8 {
+        // Always move the block, so that Memcheck reports accesses through stale pointers.
+        let new_ptr = self.allocate(size, align);
+        if new_ptr.is_null() {
+            return new_ptr;
+        }
+        ptr::copy_nonoverlapping_memory(new_ptr, ptr as *const u8, cmp::min(size, old_size));
+        self.deallocate(ptr, old_size, align);
+        new_ptr
+    }
+}
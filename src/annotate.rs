@@ -0,0 +1,166 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Helgrind and DRD annotations, which describe synchronization that the tools cannot see (e.g.
+//! in lock-free code). All of them do nothing when not run on Valgrind.
+//!
+//! Objects are identified by their address only; they are never accessed.
+//!
+//! # See also
+//! * [Hints and Tips for Effective Use of Helgrind](http://valgrind.org/docs/manual/hg-manual.html#hg-manual.effective-use). Valgrind User Manual.
+//! * [Using the ANNOTATE_* macros](http://valgrind.org/docs/manual/drd-manual.html#drd-manual.annotations). Valgrind User Manual.
+
+use std::mem;
+use std::sync::{Arc};
+
+use client_request::{client_request};
+
+// Helgrind's requests, which DRD also handles.
+static HG_RWLOCK_INIT_POST: uint = 0x4847010e;
+static HG_RWLOCK_DESTROY_PRE: uint = 0x4847010f;
+static HG_RWLOCK_LOCK_POST: uint = 0x48470111;
+static HG_RWLOCK_UNLOCK_PRE: uint = 0x48470112;
+static HG_USERSO_SEND_PRE: uint = 0x48470121;
+static HG_USERSO_RECV_POST: uint = 0x48470122;
+static HG_USERSO_FORGET_ALL: uint = 0x48470123;
+static HG_ARANGE_MAKE_UNTRACKED: uint = 0x48470127;
+static HG_ARANGE_MAKE_TRACKED: uint = 0x48470128;
+
+// DRD's own requests, numbered from ('D' << 24) | ('R' << 16).
+static DRD_START_SUPPRESSION: uint = 0x44520002;
+static DRD_FINISH_SUPPRESSION: uint = 0x44520003;
+
+/// Returns the address of `obj`.
+fn address_of<T>(obj: &T) -> uint {
+    obj as *const T as uint
+}
+
+/// Issues the request `code` with the arguments `args`.
+fn request(code: uint, args: &[uint]) {
+    unsafe { client_request(code, args); }
+}
+
+/// Announces that everything done by this thread so far happens before whatever a thread does
+/// after a matching `happens_after()` on the same object (`ANNOTATE_HAPPENS_BEFORE`).
+pub fn happens_before<T>(obj: &T) {
+    request(HG_USERSO_SEND_PRE, &[address_of(obj)]);
+}
+
+/// Announces that everything done by this thread from now on happens after whatever threads did
+/// before their `happens_before()` on the same object (`ANNOTATE_HAPPENS_AFTER`).
+pub fn happens_after<T>(obj: &T) {
+    request(HG_USERSO_RECV_POST, &[address_of(obj)]);
+}
+
+/// Forgets the happens-before relations of `obj`, e.g. before its memory is reused
+/// (`ANNOTATE_HAPPENS_BEFORE_FORGET_ALL`).
+pub fn happens_before_forget_all<T>(obj: &T) {
+    request(HG_USERSO_FORGET_ALL, &[address_of(obj)]);
+}
+
+/// Announces that `lock` is a reader-writer lock (`ANNOTATE_RWLOCK_CREATE`).
+pub fn rwlock_create<T>(lock: &T) {
+    request(HG_RWLOCK_INIT_POST, &[address_of(lock)]);
+}
+
+/// Announces that the reader-writer lock `lock` is destroyed (`ANNOTATE_RWLOCK_DESTROY`).
+pub fn rwlock_destroy<T>(lock: &T) {
+    request(HG_RWLOCK_DESTROY_PRE, &[address_of(lock)]);
+}
+
+/// Announces that this thread acquired the reader-writer lock `lock`, for writing if `is_writer`
+/// (`ANNOTATE_RWLOCK_ACQUIRED`).
+pub fn rwlock_acquired<T>(lock: &T, is_writer: bool) {
+    request(HG_RWLOCK_LOCK_POST, &[address_of(lock), is_writer as uint]);
+}
+
+/// Announces that this thread is about to release the reader-writer lock `lock`
+/// (`ANNOTATE_RWLOCK_RELEASED`).
+pub fn rwlock_released<T>(lock: &T, is_writer: bool) {
+    request(HG_RWLOCK_UNLOCK_PRE, &[address_of(lock), is_writer as uint]);
+}
+
+/// Tells Helgrind that the races on `obj` are benign, so that it stops checking the memory of
+/// `obj` (`ANNOTATE_BENIGN_RACE`).
+pub fn benign_race<T>(obj: &T) {
+    request(HG_ARANGE_MAKE_UNTRACKED, &[address_of(obj), mem::size_of::<T>()]);
+}
+
+/// Tells Helgrind to check the memory of `obj` again, after `benign_race()`.
+pub fn stop_benign_race<T>(obj: &T) {
+    request(HG_ARANGE_MAKE_TRACKED, &[address_of(obj), mem::size_of::<T>()]);
+}
+
+/// Tells DRD to ignore the accesses to `var` (`DRD_IGNORE_VAR`).
+pub fn drd_ignore_var<T>(var: &T) {
+    request(DRD_START_SUPPRESSION, &[address_of(var), mem::size_of::<T>()]);
+}
+
+/// Tells DRD to check the accesses to `var` again, after `drd_ignore_var()`
+/// (`DRD_STOP_IGNORING_VAR`).
+pub fn drd_stop_ignoring_var<T>(var: &T) {
+    request(DRD_FINISH_SUPPRESSION, &[address_of(var), mem::size_of::<T>()]);
+}
+
+/// The shared value of an `AnnotatedArc`.
+struct Shared<T> {
+    data: T,
+}
+
+#[unsafe_destructor]
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // The last reference is gone; everything that the other owners did happens before the
+        // value is dropped. Its memory may be reused for another object afterwards.
+        happens_after(self);
+        happens_before_forget_all(self);
+    }
+}
+
+/// An `Arc` whose reference counting is annotated, so that Helgrind and DRD do not report races
+/// between the last uses of the value in one thread and its destruction in another.
+pub struct AnnotatedArc<T> {
+    arc: Arc<Shared<T>>,
+}
+
+impl<T: Send + Share> AnnotatedArc<T> {
+    /// Creates an `AnnotatedArc` that owns `data`.
+    pub fn new(data: T) -> AnnotatedArc<T> {
+        AnnotatedArc {
+            arc: Arc::new(Shared {
+                data: data,
+            }),
+        }
+    }
+}
+
+impl<T: Send + Share> Clone for AnnotatedArc<T> {
+    fn clone(&self) -> AnnotatedArc<T> {
+        AnnotatedArc {
+            arc: self.arc.clone(),
+        }
+    }
+}
+
+impl<T: Send + Share> Deref<T> for AnnotatedArc<T> {
+    fn deref<'a>(&'a self) -> &'a T {
+        &self.arc.deref().data
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send + Share> Drop for AnnotatedArc<T> {
+    fn drop(&mut self) {
+        // Runs before the reference count is decremented.
+        happens_before(self.arc.deref());
+    }
+}
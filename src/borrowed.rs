@@ -0,0 +1,323 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! A zero-copy view of suppressions, borrowing from the text they were parsed from.
+//!
+//! Names, globs and extra information are slices of the input. Frame globs are also interned:
+//! each distinct glob gets an id from the parser's `GlobInterner`, so repeated globs such as
+//! `fun:malloc` compare by id and can be compiled once for the whole input. The extra
+//! information and calling context of a suppression naming several tools are shared, rather
+//! than cloned, between the suppressions of each tool. `into_owned()` (or `to_owned()`, which
+//! keeps the borrowed suppression) converts a borrowed suppression to a `Suppression` when it has
+//! to outlive the input.
+
+use std::collections::{Deque, HashMap, RingBuf};
+use std::fmt::{FormatError, Formatter, Show};
+use std::mem;
+use std::option::{Option};
+use std::rc::{Rc};
+use std::result::{Result, fold_};
+use std::str::{CharSplits};
+use std::vec::{Vec};
+
+use super::{BeforeOpeningBrace, Frame, FrameWildcard, FunFrame, ObjFrame, ParseError, ParseState, ParseTarget,
+            SrcFrame, Suppression, SuppressionType};
+use super::{check_eof, next_state, split_src_line};
+use glob::{Glob};
+
+/// A glob interned by a `GlobInterner`. Globs interned by the same interner are equal if and
+/// only if their ids are.
+#[deriving(Clone, PartialEq, Show)]
+pub struct InternedGlob<'a> {
+    /// The index of the glob in its interner.
+    pub id: uint,
+    /// The text of the glob.
+    pub text: &'a str,
+}
+
+/// Assigns an id to each distinct glob.
+pub struct GlobInterner<'a> {
+    ids: HashMap<&'a str, uint>,
+    globs: Vec<&'a str>,
+}
+
+impl<'a> GlobInterner<'a> {
+    pub fn new() -> GlobInterner<'a> {
+        GlobInterner {
+            ids: HashMap::new(),
+            globs: Vec::new(),
+        }
+    }
+
+    /// Returns the interned glob equal to `glob`, assigning it the next id if it is new.
+    pub fn intern(&mut self, glob: &'a str) -> InternedGlob<'a> {
+        let id = match self.ids.find(&glob) {
+                Some(&id) => id,
+                None => {
+                    let id = self.globs.len();
+                    self.ids.insert(glob, id);
+                    self.globs.push(glob);
+                    id
+                },
+            };
+        InternedGlob {
+            id: id,
+            text: self.globs[id],
+        }
+    }
+
+    /// The number of distinct globs.
+    pub fn len(&self) -> uint {
+        self.globs.len()
+    }
+
+    /// Returns the text of the glob with the id `id`.
+    pub fn text(&self, id: uint) -> &'a str {
+        self.globs[id]
+    }
+
+    /// Compiles each distinct glob once. The glob with the id `i` is at index `i`.
+    pub fn compile(&self) -> Vec<Glob> {
+        self.globs.iter().map(|glob| Glob::new(*glob)).collect()
+    }
+}
+
+/// A frame of a calling context, borrowing its glob.
+#[deriving(Clone, PartialEq)]
+pub enum BorrowedFrame<'a> {
+    /// A frame-level wildcard, represented by `'...'`.
+    BorrowedFrameWildcard,
+    /// An object frame, with the glob for the path to the object file.
+    BorrowedObjFrame(InternedGlob<'a>),
+    /// A function frame, with the glob for the name of the function.
+    BorrowedFunFrame(InternedGlob<'a>),
+    /// A source frame, with the glob for the name of the source file and the line number, if any.
+    BorrowedSrcFrame(InternedGlob<'a>, Option<uint>),
+}
+
+impl<'a> BorrowedFrame<'a> {
+    /// Parses a line of a calling context: `...`, `obj:<glob>`, `fun:<glob>` or
+    /// `src:<glob>[:<line>]`, interning the glob with `interner`. Returns `None` if `line` is not a calling context line.
+    pub fn from_line(line: &'a str, interner: &mut GlobInterner<'a>) -> Option<BorrowedFrame<'a>> {
+        let line = line.trim();
+        if line == "..." {
+            Some(BorrowedFrameWildcard)
+        } else if line.starts_with("obj:") {
+            Some(BorrowedObjFrame(interner.intern(line.slice_from(4).trim_left())))
+        } else if line.starts_with("fun:") {
+            Some(BorrowedFunFrame(interner.intern(line.slice_from(4).trim_left())))
+        } else if line.starts_with("src:") {
+            let (glob, opt_line) = split_src_line(line.slice_from(4).trim_left());
+            Some(BorrowedSrcFrame(interner.intern(glob), opt_line))
+        } else {
+            None
+        }
+    }
+
+    /// Converts this frame into a `Frame`.
+    pub fn into_owned(self) -> Frame {
+        self.to_owned()
+    }
+
+    /// Copies this frame into a `Frame`.
+    pub fn to_owned(&self) -> Frame {
+        match *self {
+            BorrowedFrameWildcard => FrameWildcard,
+            BorrowedObjFrame(ref glob) => ObjFrame { glob: glob.text.to_string() },
+            BorrowedFunFrame(ref glob) => FunFrame { glob: glob.text.to_string() },
+            BorrowedSrcFrame(ref glob, opt_line) => SrcFrame { glob: glob.text.to_string(), opt_line: opt_line },
+        }
+    }
+}
+
+impl<'a> Show for BorrowedFrame<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        match *self {
+            BorrowedFrameWildcard => write!(fmt, "..."),
+            BorrowedObjFrame(ref glob) => write!(fmt, "obj:{}", glob.text),
+            BorrowedFunFrame(ref glob) => write!(fmt, "fun:{}", glob.text),
+            BorrowedSrcFrame(ref glob, None) => write!(fmt, "src:{}", glob.text),
+            BorrowedSrcFrame(ref glob, Some(line)) => write!(fmt, "src:{}:{:u}", glob.text, line),
+        }
+    }
+}
+
+/// A single Valgrind suppression, borrowing from the text it was parsed from.
+#[deriving(Clone)]
+pub struct BorrowedSuppression<'a> {
+    /// The name of the suppression.
+    pub name: &'a str,
+    /// The name of the tool (e.g. `Memcheck`).
+    pub tool_name: &'a str,
+    /// The name of the suppression type within the tool (e.g. `Addr8`).
+    pub suppression_type: &'a str,
+    /// Any extra information, where used by the suppression type. This is empty if the
+    /// suppression has no extra information.
+    pub extra_info: Rc<Vec<&'a str>>,
+    /// The calling context of the suppression.
+    pub frames: Rc<Vec<BorrowedFrame<'a>>>,
+}
+
+impl<'a> BorrowedSuppression<'a> {
+    /// The type of the suppression.
+    pub fn type_(&self) -> SuppressionType {
+        SuppressionType::from_parts(self.tool_name, self.suppression_type)
+    }
+
+    /// Converts this suppression into a `Suppression`. The text is copied either way, as it is
+    /// borrowed from the input.
+    pub fn into_owned(self) -> Suppression {
+        self.to_owned()
+    }
+
+    /// Copies this suppression into a `Suppression`.
+    pub fn to_owned(&self) -> Suppression {
+        Suppression {
+            name: self.name.to_string(),
+            type_: self.type_(),
+            opt_extra_info: if self.extra_info.is_empty() {
+                    None
+                } else {
+                    Some(self.extra_info.iter().map(|line| line.to_string()).collect())
+                },
+            frames: self.frames.iter().map(|frame| frame.to_owned()).collect(),
+        }
+    }
+}
+
+impl<'a> Show for BorrowedSuppression<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        (writeln!(fmt, "{{"))
+            .and_then(|()| -> Result<(), FormatError> {
+                writeln!(fmt, "   {}", self.name)
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                writeln!(fmt, "   {}:{}", self.tool_name, self.suppression_type)
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                fold_(self.extra_info.iter().map(|line| -> Result<(), FormatError> {
+                    writeln!(fmt, "   {}", line)
+                }))
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                fold_(self.frames.iter().map(|frame| -> Result<(), FormatError> {
+                    writeln!(fmt, "   {}", frame)
+                }))
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                write!(fmt, "}}")
+            })
+    }
+}
+
+/// Collects the suppressions completed by the parser, interning their globs.
+struct BorrowedTarget<'a> {
+    interner: GlobInterner<'a>,
+    completed: RingBuf<BorrowedSuppression<'a>>,
+}
+
+impl<'a> ParseTarget<&'a str, BorrowedFrame<'a>> for BorrowedTarget<'a> {
+    fn slice(&mut self, line: &&'a str, from: uint, to: uint) -> &'a str {
+        (*line).slice(from, to)
+    }
+
+    fn frame(&mut self, line: &&'a str) -> Option<BorrowedFrame<'a>> {
+        BorrowedFrame::from_line(*line, &mut self.interner)
+    }
+
+    fn complete(&mut self, _opening_brace_lineno: uint, name: &'a str, tool_names: Vec<&'a str>, suppression_type: &'a str,
+                opt_extra_info: Option<Vec<&'a str>>, frames: Vec<BorrowedFrame<'a>>) {
+        let extra_info = Rc::new(opt_extra_info.unwrap_or(Vec::new()));
+        let frames = Rc::new(frames);
+        for tool_name in tool_names.iter() {
+            self.completed.push_back(BorrowedSuppression {
+                name: name,
+                tool_name: *tool_name,
+                suppression_type: suppression_type,
+                extra_info: extra_info.clone(),
+                frames: frames.clone(),
+            });
+        }
+    }
+}
+
+/// Parses borrowed suppressions from `text` in Valgrind suppression syntax. The syntax accepted
+/// and the errors returned are the same as those of `Suppressions::parse`; parsing stops after
+/// the first error.
+pub fn parse_str<'a>(text: &'a str) -> BorrowedSuppressions<'a> {
+    BorrowedSuppressions {
+        lines: text.split('\n'),
+        lineno: 0,
+        state: BeforeOpeningBrace,
+        target: BorrowedTarget {
+            interner: GlobInterner::new(),
+            completed: RingBuf::new(),
+        },
+        done: false,
+    }
+}
+
+/// An iterator over the suppressions parsed from a string.
+pub struct BorrowedSuppressions<'a> {
+    lines: CharSplits<'a, char>,
+    lineno: uint,
+    state: ParseState<&'a str, BorrowedFrame<'a>>,
+    target: BorrowedTarget<'a>,
+    done: bool,
+}
+
+impl<'a> BorrowedSuppressions<'a> {
+    /// The interner of the globs of the suppressions parsed so far.
+    pub fn interner<'b>(&'b self) -> &'b GlobInterner<'a> {
+        &self.target.interner
+    }
+}
+
+impl<'a> Iterator<Result<BorrowedSuppression<'a>, ParseError>> for BorrowedSuppressions<'a> {
+    fn next(&mut self) -> Option<Result<BorrowedSuppression<'a>, ParseError>> {
+        loop {
+            match self.target.completed.pop_front() {
+                Some(suppression) => return Some(Ok(suppression)),
+                None => (),
+            }
+            if self.done {
+                return None;
+            }
+
+            let state = mem::replace(&mut self.state, BeforeOpeningBrace);
+            let state_res = match self.lines.next() {
+                    None => {
+                        self.done = true;
+                        check_eof(state).map(|()| BeforeOpeningBrace)
+                    },
+                    Some(line) => {
+                        self.lineno = self.lineno + 1;
+
+                        let trimmed_line = line.trim();
+                        if !trimmed_line.is_empty() && !trimmed_line.starts_with("#") {
+                            let column = line.char_len() - line.trim_left().char_len() + 1;
+                            next_state(state, self.lineno, column, trimmed_line, &mut self.target)
+                        } else {
+                            Ok(state)
+                        }
+                    },
+                };
+            match state_res {
+                Ok(state) => self.state = state,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! A builder for constructing suppressions programmatically.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{Frame, FrameWildcard, FunFrame, ObjFrame, SrcFrame, Suppression, SuppressionType};
+use super::{MemcheckAddr, MemcheckCond, MemcheckFree, MemcheckLeak, MemcheckOverlap, MemcheckParam, MemcheckValue, OtherType};
+use report::{LeakKind};
+
+/// Holds information about the first invalid call made on a `SuppressionBuilder`.
+#[deriving(Clone, PartialEq)]
+pub struct BuildError {
+    /// Description of the error.
+    pub message: String,
+}
+
+impl Show for BuildError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}", self.message.as_slice())
+    }
+}
+
+/// Builds a `Suppression`, validating each call as it is made. The first invalid call is
+/// remembered and returned by `build()`; later calls are ignored.
+pub struct SuppressionBuilder {
+    name: String,
+    opt_type: Option<SuppressionType>,
+    opt_extra_info: Option<Vec<String>>,
+    frames: Vec<Frame>,
+    opt_error: Option<BuildError>,
+}
+
+impl Suppression {
+    /// Starts building a suppression named `name`.
+    pub fn builder(name: &str) -> SuppressionBuilder {
+        let opt_error = if name.trim().is_empty() {
+                Some(String::from_str("the suppression name cannot be empty"))
+            } else if name.contains_char('}') {
+                Some(String::from_str("the suppression name cannot contain a closing brace '}'"))
+            } else if name.contains_char('\n') {
+                Some(String::from_str("the suppression name cannot contain a newline"))
+            } else {
+                None
+            };
+        SuppressionBuilder {
+            name: name.trim().to_string(),
+            opt_type: None,
+            opt_extra_info: None,
+            frames: Vec::new(),
+            opt_error: opt_error.map(|message| BuildError { message: message }),
+        }
+    }
+}
+
+/// Returns whether `n` is a valid access size of a Memcheck `Addr` or `Value` suppression.
+fn is_valid_size(n: uint) -> bool {
+    match n {
+        1 | 2 | 4 | 8 | 16 | 32 => true,
+        _ => false,
+    }
+}
+
+impl SuppressionBuilder {
+
+    fn fail(mut self, message: String) -> SuppressionBuilder {
+        if self.opt_error.is_none() {
+            self.opt_error = Some(BuildError {
+                message: message,
+            });
+        }
+        self
+    }
+
+    fn type_(mut self, type_: SuppressionType) -> SuppressionBuilder {
+        if self.opt_error.is_some() {
+            return self;
+        }
+        match self.opt_type {
+            Some(ref existing_type) => {
+                let message = format!("the suppression type was already set to {}", existing_type);
+                return self.fail(message);
+            },
+            None => (),
+        }
+        self.opt_type = Some(type_);
+        self
+    }
+
+    fn extra_info(mut self, line: &str) -> SuppressionBuilder {
+        if self.opt_error.is_some() {
+            return self;
+        }
+        if !self.frames.is_empty() {
+            return self.fail(String::from_str("extra information must come before the calling context"));
+        }
+        if line.trim().is_empty() || line.contains_char('\n') || line.contains_char('}') {
+            return self.fail(format!("invalid extra information line '{}'", line));
+        }
+        match self.opt_extra_info {
+            None => self.opt_extra_info = Some(vec![line.trim().to_string()]),
+            Some(ref mut extra_info) => extra_info.push(line.trim().to_string()),
+        }
+        self
+    }
+
+    fn frame(mut self, frame: Frame, glob: &str) -> SuppressionBuilder {
+        if self.opt_error.is_some() {
+            return self;
+        }
+        if self.opt_type.is_none() {
+            return self.fail(String::from_str("the suppression type must be set before the calling context"));
+        }
+        if glob.trim().is_empty() || glob.contains_char('\n') {
+            return self.fail(format!("invalid glob '{}'", glob));
+        }
+        self.frames.push(frame);
+        self
+    }
+
+    /// Sets the type to `Memcheck:Addr<n>`, where `n` is the size of the access.
+    pub fn memcheck_addr(self, n: uint) -> SuppressionBuilder {
+        if !is_valid_size(n) {
+            return self.fail(format!("invalid access size {:u} for a Memcheck:Addr suppression", n));
+        }
+        self.type_(MemcheckAddr(n))
+    }
+
+    /// Sets the type to `Memcheck:Cond`.
+    pub fn memcheck_cond(self) -> SuppressionBuilder {
+        self.type_(MemcheckCond)
+    }
+
+    /// Sets the type to `Memcheck:Free`.
+    pub fn memcheck_free(self) -> SuppressionBuilder {
+        self.type_(MemcheckFree)
+    }
+
+    /// Sets the type to `Memcheck:Leak`.
+    pub fn memcheck_leak(self) -> SuppressionBuilder {
+        self.type_(MemcheckLeak)
+    }
+
+    /// Sets the type to `Memcheck:Overlap`.
+    pub fn memcheck_overlap(self) -> SuppressionBuilder {
+        self.type_(MemcheckOverlap)
+    }
+
+    /// Sets the type to `Memcheck:Param` with the system call parameter `param` (e.g.
+    /// `write(buf)`) as extra information.
+    pub fn memcheck_param(self, param: &str) -> SuppressionBuilder {
+        self.type_(MemcheckParam).extra_info(param)
+    }
+
+    /// Sets the type to `Memcheck:Value<n>`, where `n` is the size of the value.
+    pub fn memcheck_value(self, n: uint) -> SuppressionBuilder {
+        if !is_valid_size(n) {
+            return self.fail(format!("invalid value size {:u} for a Memcheck:Value suppression", n));
+        }
+        self.type_(MemcheckValue(n))
+    }
+
+    /// Sets the type to the suppression type `suppression_type` of the tool `tool_name` (e.g.
+    /// `Helgrind` and `Race`).
+    pub fn tool_type(self, tool_name: &str, suppression_type: &str) -> SuppressionBuilder {
+        if tool_name.is_empty() || tool_name.contains_char(':') || tool_name.contains_char(',') || suppression_type.is_empty() {
+            return self.fail(format!("invalid suppression type '{}:{}'", tool_name, suppression_type));
+        }
+        let type_ = SuppressionType::from_parts(tool_name, suppression_type);
+        self.type_(type_)
+    }
+
+    /// Restricts a `Memcheck:Leak` suppression to the leak kinds `kinds`, by adding a
+    /// `match-leak-kinds:` line of extra information.
+    pub fn leak_kinds(self, kinds: &[LeakKind]) -> SuppressionBuilder {
+        if self.opt_type != Some(MemcheckLeak) {
+            return self.fail(String::from_str("leak kinds can only be set on a Memcheck:Leak suppression"));
+        }
+        let names: Vec<&'static str> = kinds.iter().map(|kind| kind.name()).collect();
+        let line = if names.is_empty() {
+                String::from_str("match-leak-kinds: none")
+            } else {
+                format!("match-leak-kinds: {}", names.connect(","))
+            };
+        self.extra_info(line.as_slice())
+    }
+
+    /// Appends a `fun:` frame matching function names against `glob`.
+    pub fn fun(self, glob: &str) -> SuppressionBuilder {
+        let frame = FunFrame {
+            glob: glob.trim().to_string(),
+        };
+        self.frame(frame, glob)
+    }
+
+    /// Appends an `obj:` frame matching object file paths against `glob`.
+    pub fn obj(self, glob: &str) -> SuppressionBuilder {
+        let frame = ObjFrame {
+            glob: glob.trim().to_string(),
+        };
+        self.frame(frame, glob)
+    }
+
+    /// Appends a `src:` frame matching source file names against `glob` and, if given, the line
+    /// number against `opt_line`.
+    pub fn src(self, glob: &str, opt_line: Option<uint>) -> SuppressionBuilder {
+        let frame = SrcFrame {
+            glob: glob.trim().to_string(),
+            opt_line: opt_line,
+        };
+        self.frame(frame, glob)
+    }
+
+    /// Appends a `...` frame, matching zero or more stack frames.
+    pub fn ellipsis(self) -> SuppressionBuilder {
+        self.frame(FrameWildcard, "...")
+    }
+
+    /// Builds the suppression, or returns the first error.
+    pub fn build(self) -> Result<Suppression, BuildError> {
+        match self.opt_error {
+            Some(error) => return Err(error),
+            None => (),
+        }
+        let type_ = match self.opt_type {
+                None => return Err(BuildError { message: String::from_str("no suppression type was set") }),
+                Some(type_) => type_,
+            };
+        if self.frames.is_empty() {
+            return Err(BuildError { message: String::from_str("the calling context must have at least one frame") });
+        }
+        Ok(Suppression {
+            name: self.name,
+            type_: type_,
+            opt_extra_info: self.opt_extra_info,
+            frames: self.frames,
+        })
+    }
+}
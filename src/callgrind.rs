@@ -0,0 +1,155 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Callgrind client requests, which limit the profiling to parts of a program. All of them do
+//! nothing when not run on Valgrind.
+//!
+//! `CollectGuard`, `collect()` and `collect_and_dump()` toggle the collection of events, so they
+//! require Callgrind to be run with `--collect-atstart=no`; otherwise they switch collection off
+//! for the guarded code instead of on. Likewise, `InstrumentationGuard` requires
+//! `--instr-atstart=no`. Guards may be nested: only the outermost guard switches collection or
+//! instrumentation on and off.
+//!
+//! # See also
+//! * [Callgrind specific client requests](http://valgrind.org/docs/manual/cl-manual.html#cl-manual.clientrequests). Valgrind User Manual.
+
+use std::c_str::{ToCStr};
+use std::sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+use client_request::{client_request};
+
+static DUMP_STATS: uint = 0x43540000;
+static ZERO_STATS: uint = 0x43540001;
+static TOGGLE_COLLECT: uint = 0x43540002;
+static DUMP_STATS_AT: uint = 0x43540003;
+static START_INSTRUMENTATION: uint = 0x43540004;
+static STOP_INSTRUMENTATION: uint = 0x43540005;
+
+/// The number of live `InstrumentationGuard`s. Instrumentation is global to the process.
+static INSTRUMENTATION_DEPTH: AtomicUint = INIT_ATOMIC_UINT;
+
+// The number of live `CollectGuard`s of this task. Callgrind keeps the collection state of
+// each thread separately.
+local_data_key!(COLLECT_DEPTH: uint)
+
+/// Issues the request `code` with the arguments `args`.
+fn request(code: uint, args: &[uint]) {
+    unsafe { client_request(code, args); }
+}
+
+/// Dumps the profile collected so far to a new file and zeroes the counters.
+pub fn dump_stats() {
+    request(DUMP_STATS, &[]);
+}
+
+/// Dumps the profile collected so far like `dump_stats()`, giving `reason` as the trigger of the
+/// dump in the file.
+pub fn dump_stats_at(reason: &str) {
+    // Callgrind copies the string.
+    reason.with_c_str(|reason| request(DUMP_STATS_AT, &[reason as uint]));
+}
+
+/// Zeroes the counters of the profile collected so far.
+pub fn zero_stats() {
+    request(ZERO_STATS, &[]);
+}
+
+/// Switches the collection of events on if it is off, and off if it is on. Collection is on at
+/// the start, unless Callgrind is run with `--collect-atstart=no`.
+pub fn toggle_collect() {
+    request(TOGGLE_COLLECT, &[]);
+}
+
+/// Starts the instrumentation of the program, if Callgrind was run with
+/// `--instr-atstart=no`.
+pub fn start_instrumentation() {
+    request(START_INSTRUMENTATION, &[]);
+}
+
+/// Stops the instrumentation of the program, so that it runs faster but nothing is profiled.
+pub fn stop_instrumentation() {
+    request(STOP_INSTRUMENTATION, &[]);
+}
+
+/// A guard that instruments the program until it is dropped. Callgrind should be run with
+/// `--instr-atstart=no`. Only the outermost of nested guards starts and stops the
+/// instrumentation.
+pub struct InstrumentationGuard {
+    // Prevents construction outside of `new()`.
+    _private: (),
+}
+
+impl InstrumentationGuard {
+    /// Starts the instrumentation, unless another guard already did.
+    pub fn new() -> InstrumentationGuard {
+        if INSTRUMENTATION_DEPTH.fetch_add(1, SeqCst) == 0 {
+            start_instrumentation();
+        }
+        InstrumentationGuard {
+            _private: (),
+        }
+    }
+}
+
+impl Drop for InstrumentationGuard {
+    fn drop(&mut self) {
+        if INSTRUMENTATION_DEPTH.fetch_sub(1, SeqCst) == 1 {
+            stop_instrumentation();
+        }
+    }
+}
+
+/// A guard that toggles the collection of events when created and again when dropped. Callgrind
+/// must be run with `--collect-atstart=no`, so that events are only collected while guarded.
+/// Only the outermost of the nested guards of a task toggles the collection.
+pub struct CollectGuard {
+    // Prevents construction outside of `new()`.
+    _private: (),
+}
+
+impl CollectGuard {
+    /// Toggles the collection of events on, unless another guard of this task already did.
+    pub fn new() -> CollectGuard {
+        let depth = COLLECT_DEPTH.get().map(|depth| *depth).unwrap_or(0);
+        COLLECT_DEPTH.replace(Some(depth + 1));
+        if depth == 0 {
+            toggle_collect();
+        }
+        CollectGuard {
+            _private: (),
+        }
+    }
+}
+
+impl Drop for CollectGuard {
+    fn drop(&mut self) {
+        let depth = COLLECT_DEPTH.get().map(|depth| *depth).unwrap_or(1) - 1;
+        COLLECT_DEPTH.replace(Some(depth));
+        if depth == 0 {
+            toggle_collect();
+        }
+    }
+}
+
+/// Runs `f`, collecting its events. Callgrind must be run with `--collect-atstart=no`.
+pub fn collect<T>(f: || -> T) -> T {
+    let _guard = CollectGuard::new();
+    f()
+}
+
+/// Runs `f`, collecting its events, then dumps them to their own file with `name` as the trigger.
+/// Callgrind must be run with `--collect-atstart=no`.
+pub fn collect_and_dump<T>(name: &str, f: || -> T) -> T {
+    let result = collect(f);
+    dump_stats_at(name);
+    result
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Client requests, which a program uses to talk to Valgrind, without linking to `valgrind.h`.
+//!
+//! A client request is a special instruction sequence that does nothing when run natively, in
+//! which case the request returns its default value. Client requests are only implemented for
+//! x86_64; on other architectures, every request returns its default value.
+//!
+//! # See also
+//! * [The Client Request mechanism](http://valgrind.org/docs/manual/manual-core-adv.html#manual-core-adv.clientreq). Valgrind User Manual.
+
+/// The request code of `RUNNING_ON_VALGRIND`.
+pub static RUNNING_ON_VALGRIND: uint = 0x1001;
+
+/// The request code of `COUNT_ERRORS`.
+pub static COUNT_ERRORS: uint = 0x1201;
+
+/// Returns the base of the request codes of a tool, from the two characters that identify it
+/// (e.g. `'M'` and `'C'` for Memcheck), like `VG_USERREQ_TOOL_BASE`.
+pub fn tool_base(a: char, b: char) -> uint {
+    ((a as uint) & 0xff) << 24 | ((b as uint) & 0xff) << 16
+}
+
+/// Issues the client request `code` with the arguments `args` (at most five), returning `default`
+/// if not run on Valgrind.
+///
+/// This is unsafe because a request may make Valgrind read or write memory given by the
+/// arguments.
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn client_request_with_default(default: uint, code: uint, args: &[uint]) -> uint {
+    if args.len() > 5 {
+        fail!("a client request takes at most 5 arguments, not {:u}", args.len());
+    }
+    let mut request = [code, 0, 0, 0, 0, 0];
+    for (i, arg) in args.iter().enumerate() {
+        request[i + 1] = *arg;
+    }
+    let result: uint;
+    // The "special instruction preamble" of valgrind.h: the rotations of %rdi add up to 128 bits,
+    // so they do nothing natively. Valgrind recognizes the sequence, reads the request from the
+    // array at %rax and puts the result in %rdx.
+    asm!("rolq $$3, %rdi; rolq $$13, %rdi
+          rolq $$61, %rdi; rolq $$51, %rdi
+          xchgq %rbx, %rbx"
+         : "={rdx}"(result)
+         : "{rax}"(request.as_ptr()), "{rdx}"(default)
+         : "cc", "memory", "rdi"
+         : "volatile");
+    result
+}
+
+/// Issues the client request `code` with the arguments `args` (at most five), returning `default`
+/// if not run on Valgrind.
+///
+/// This is unsafe because a request may make Valgrind read or write memory given by the
+/// arguments.
+#[cfg(not(target_arch = "x86_64"))]
+pub unsafe fn client_request_with_default(default: uint, code: uint, args: &[uint]) -> uint {
+    if args.len() > 5 {
+        fail!("a client request takes at most 5 arguments, not {:u}", args.len());
+    }
+    let _ = code;
+    default
+}
+
+/// Issues the client request `code` with the arguments `args` (at most five), returning 0 if not
+/// run on Valgrind.
+pub unsafe fn client_request(code: uint, args: &[uint]) -> uint {
+    client_request_with_default(0, code, args)
+}
+
+/// Returns the number of Valgrinds that the program is running on: 0 when run natively, 1 when
+/// run on Valgrind, and more when Valgrind itself is run on Valgrind.
+pub fn running_on_valgrind() -> uint {
+    unsafe { client_request(RUNNING_ON_VALGRIND, &[]) }
+}
+
+/// Returns the number of errors that Valgrind has reported so far, not counting suppressed
+/// errors. This is always 0 when not run on Valgrind.
+pub fn count_errors() -> uint {
+    unsafe { client_request(COUNT_ERRORS, &[]) }
+}
+
+/// Runs `f`, failing if Valgrind reported errors while it ran. Unlike `--error-exitcode=`, this
+/// fails only the test that caused the errors. This does nothing more than running `f` when not
+/// run on Valgrind.
+pub fn assert_no_valgrind_errors<T>(f: || -> T) -> T {
+    let before = count_errors();
+    let result = f();
+    let after = count_errors();
+    if after > before {
+        fail!("Valgrind reported {:u} new error(s)", after - before);
+    }
+    result
+}
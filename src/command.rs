@@ -0,0 +1,499 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! A typed builder for running programs under Valgrind.
+//!
+//! # See also
+//! * [Core Command-line Options](http://valgrind.org/docs/manual/manual-core.html#manual-core.options). Valgrind User Manual.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::io::{File, IoError, TempDir};
+use std::io::process::{Command, ProcessExit};
+use std::option::{Option};
+use std::os;
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use merge::{LogFileTemplate, TemplateError};
+use report::{LeakKind, Report};
+use xml::{XmlError, parse_report};
+
+/// A Valgrind tool, as selected by `--tool=`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Tool {
+    MemcheckTool,
+    HelgrindTool,
+    DrdTool,
+    MassifTool,
+    CallgrindTool,
+    /// Any other tool, by name (e.g. `cachegrind`).
+    OtherTool(String),
+}
+
+impl Tool {
+    /// The name of the tool as given to `--tool=`.
+    pub fn name<'a>(&'a self) -> &'a str {
+        match *self {
+            MemcheckTool => "memcheck",
+            HelgrindTool => "helgrind",
+            DrdTool => "drd",
+            MassifTool => "massif",
+            CallgrindTool => "callgrind",
+            OtherTool(ref name) => name.as_slice(),
+        }
+    }
+}
+
+/// The value of `--gen-suppressions=`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum GenSuppressions {
+    GenSuppressionsNo,
+    GenSuppressionsYes,
+    GenSuppressionsAll,
+}
+
+/// The value of Memcheck's `--leak-check=`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum LeakCheck {
+    LeakCheckNo,
+    LeakCheckSummary,
+    LeakCheckYes,
+    LeakCheckFull,
+}
+
+/// The value of Helgrind's `--history-level=`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum HistoryLevel {
+    HistoryNone,
+    HistoryApprox,
+    HistoryFull,
+}
+
+/// An error generated while running a `ValgrindCommand`.
+pub enum RunError {
+    /// An option was set that does not apply to the selected tool.
+    ToolMismatch(String),
+    /// The XML output file is not a valid template.
+    InvalidTemplate(TemplateError),
+    /// Spawning Valgrind, or reading its output, failed.
+    RunIoError(IoError),
+    /// The XML output of Valgrind could not be parsed.
+    RunXmlError(XmlError),
+}
+
+impl Show for RunError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        match *self {
+            ToolMismatch(ref message) => write!(fmt, "{}", message.as_slice()),
+            InvalidTemplate(ref e) => write!(fmt, "invalid XML output file: {}", e),
+            RunIoError(ref e) => write!(fmt, "{}", e),
+            RunXmlError(ref e) => write!(fmt, "invalid XML output: {}", e),
+        }
+    }
+}
+
+/// The result of running a `ValgrindCommand`.
+pub struct ValgrindOutput {
+    /// The exit status of Valgrind, which is the exit status of the program unless
+    /// `--error-exitcode=` applies.
+    pub status: ProcessExit,
+    /// The standard output of the program.
+    pub stdout: Vec<u8>,
+    /// The standard error of Valgrind and the program.
+    pub stderr: Vec<u8>,
+    /// The report parsed from the XML output, if XML output was enabled.
+    pub opt_report: Option<Report>,
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b { "yes" } else { "no" }
+}
+
+/// A builder of a Valgrind command line, which runs a program under Valgrind.
+#[deriving(Clone)]
+pub struct ValgrindCommand {
+    valgrind: Path,
+    tool: Tool,
+    program: Path,
+    program_args: Vec<String>,
+    core_options: Vec<String>,
+    /// Tool-specific options, with the tool that each applies to.
+    tool_options: Vec<(Tool, String)>,
+    xml: bool,
+    opt_xml_file: Option<Path>,
+    demangle: bool,
+}
+
+impl ValgrindCommand {
+
+    /// Creates a command that runs `program` under Memcheck. The `valgrind` executable is looked
+    /// up on the `PATH`.
+    pub fn new(program: Path) -> ValgrindCommand {
+        ValgrindCommand {
+            valgrind: Path::new("valgrind"),
+            tool: MemcheckTool,
+            program: program,
+            program_args: Vec::new(),
+            core_options: Vec::new(),
+            tool_options: Vec::new(),
+            xml: false,
+            opt_xml_file: None,
+            demangle: false,
+        }
+    }
+
+    /// Sets the path to the `valgrind` executable.
+    pub fn valgrind<'a>(&'a mut self, valgrind: Path) -> &'a mut ValgrindCommand {
+        self.valgrind = valgrind;
+        self
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg<'a>(&'a mut self, arg: &str) -> &'a mut ValgrindCommand {
+        self.program_args.push(arg.to_string());
+        self
+    }
+
+    /// Adds arguments to pass to the program.
+    pub fn args<'a>(&'a mut self, args: &[&str]) -> &'a mut ValgrindCommand {
+        self.program_args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    /// Adds a Valgrind option that has no typed setter, e.g. `--max-stackframe=4000000`.
+    pub fn option<'a>(&'a mut self, option: &str) -> &'a mut ValgrindCommand {
+        self.core_options.push(option.to_string());
+        self
+    }
+
+    fn tool_option<'a>(&'a mut self, tool: Tool, option: String) -> &'a mut ValgrindCommand {
+        self.tool_options.push((tool, option));
+        self
+    }
+
+    /// Sets the tool (`--tool=`).
+    pub fn tool<'a>(&'a mut self, tool: Tool) -> &'a mut ValgrindCommand {
+        self.tool = tool;
+        self
+    }
+
+    /// Adds a suppressions file (`--suppressions=`).
+    pub fn suppressions<'a>(&'a mut self, path: &Path) -> &'a mut ValgrindCommand {
+        self.core_options.push(format!("--suppressions={}", path.display()));
+        self
+    }
+
+    /// Sets whether suppressions are generated for errors (`--gen-suppressions=`).
+    pub fn gen_suppressions<'a>(&'a mut self, gen_suppressions: GenSuppressions) -> &'a mut ValgrindCommand {
+        let value = match gen_suppressions {
+                GenSuppressionsNo => "no",
+                GenSuppressionsYes => "yes",
+                GenSuppressionsAll => "all",
+            };
+        self.core_options.push(format!("--gen-suppressions={}", value));
+        self
+    }
+
+    /// Enables or disables XML output (`--xml=`). If XML output is enabled without an XML output
+    /// file, `run()` writes it to a temporary file.
+    pub fn xml<'a>(&'a mut self, xml: bool) -> &'a mut ValgrindCommand {
+        self.xml = xml;
+        self
+    }
+
+    /// Sets the XML output file (`--xml-file=`), which may contain `%p` and `%q{VAR}`. `run()`
+    /// expands these for the process ID of Valgrind to find the file.
+    pub fn xml_file<'a>(&'a mut self, path: Path) -> &'a mut ValgrindCommand {
+        self.opt_xml_file = Some(path);
+        self
+    }
+
+    /// Sets whether function names are demangled in the output (`--demangle=`). This is off by
+    /// default, unlike in Valgrind: suppressions are matched against mangled names, so the
+    /// reports of a command must have mangled names for `Suppression::matches()`, `simulate()`
+    /// and the generated suppressions of `HelgrindError::suppression()` to agree with Valgrind.
+    pub fn demangle<'a>(&'a mut self, demangle: bool) -> &'a mut ValgrindCommand {
+        self.demangle = demangle;
+        self
+    }
+
+    /// Sets the log file (`--log-file=`), which may contain `%p` and `%q{VAR}`.
+    pub fn log_file<'a>(&'a mut self, path: &Path) -> &'a mut ValgrindCommand {
+        self.core_options.push(format!("--log-file={}", path.display()));
+        self
+    }
+
+    /// Sets the exit code to use if errors were reported (`--error-exitcode=`).
+    pub fn error_exitcode<'a>(&'a mut self, code: int) -> &'a mut ValgrindCommand {
+        self.core_options.push(format!("--error-exitcode={:d}", code));
+        self
+    }
+
+    /// Sets the maximum number of entries in stack traces (`--num-callers=`).
+    pub fn num_callers<'a>(&'a mut self, n: uint) -> &'a mut ValgrindCommand {
+        self.core_options.push(format!("--num-callers={:u}", n));
+        self
+    }
+
+    /// Sets whether child processes are traced (`--trace-children=`).
+    pub fn trace_children<'a>(&'a mut self, trace_children: bool) -> &'a mut ValgrindCommand {
+        self.core_options.push(format!("--trace-children={}", yes_no(trace_children)));
+        self
+    }
+
+    /// Sets Memcheck's `--leak-check=`.
+    pub fn leak_check<'a>(&'a mut self, leak_check: LeakCheck) -> &'a mut ValgrindCommand {
+        let value = match leak_check {
+                LeakCheckNo => "no",
+                LeakCheckSummary => "summary",
+                LeakCheckYes => "yes",
+                LeakCheckFull => "full",
+            };
+        self.tool_option(MemcheckTool, format!("--leak-check={}", value))
+    }
+
+    /// Sets Memcheck's `--show-leak-kinds=`.
+    pub fn show_leak_kinds<'a>(&'a mut self, kinds: &[LeakKind]) -> &'a mut ValgrindCommand {
+        let names: Vec<&'static str> = kinds.iter().map(|kind| kind.name()).collect();
+        let value = if names.is_empty() { String::from_str("none") } else { names.connect(",") };
+        self.tool_option(MemcheckTool, format!("--show-leak-kinds={}", value))
+    }
+
+    /// Sets Memcheck's `--track-origins=`.
+    pub fn track_origins<'a>(&'a mut self, track_origins: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(MemcheckTool, format!("--track-origins={}", yes_no(track_origins)))
+    }
+
+    /// Sets Helgrind's `--history-level=`.
+    pub fn history_level<'a>(&'a mut self, history_level: HistoryLevel) -> &'a mut ValgrindCommand {
+        let value = match history_level {
+                HistoryNone => "none",
+                HistoryApprox => "approx",
+                HistoryFull => "full",
+            };
+        self.tool_option(HelgrindTool, format!("--history-level={}", value))
+    }
+
+    /// Sets Helgrind's `--conflict-cache-size=`.
+    pub fn conflict_cache_size<'a>(&'a mut self, n: uint) -> &'a mut ValgrindCommand {
+        self.tool_option(HelgrindTool, format!("--conflict-cache-size={:u}", n))
+    }
+
+    /// Sets DRD's `--check-stack-var=`.
+    pub fn check_stack_var<'a>(&'a mut self, check_stack_var: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(DrdTool, format!("--check-stack-var={}", yes_no(check_stack_var)))
+    }
+
+    /// Sets DRD's `--exclusive-threshold=`, in milliseconds.
+    pub fn exclusive_threshold<'a>(&'a mut self, ms: uint) -> &'a mut ValgrindCommand {
+        self.tool_option(DrdTool, format!("--exclusive-threshold={:u}", ms))
+    }
+
+    /// Sets DRD's `--shared-threshold=`, in milliseconds.
+    pub fn shared_threshold<'a>(&'a mut self, ms: uint) -> &'a mut ValgrindCommand {
+        self.tool_option(DrdTool, format!("--shared-threshold={:u}", ms))
+    }
+
+    /// Sets Massif's `--massif-out-file=`.
+    pub fn massif_out_file<'a>(&'a mut self, path: &Path) -> &'a mut ValgrindCommand {
+        self.tool_option(MassifTool, format!("--massif-out-file={}", path.display()))
+    }
+
+    /// Sets Massif's `--stacks=`.
+    pub fn stacks<'a>(&'a mut self, stacks: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(MassifTool, format!("--stacks={}", yes_no(stacks)))
+    }
+
+    /// Sets Massif's `--depth=`.
+    pub fn depth<'a>(&'a mut self, depth: uint) -> &'a mut ValgrindCommand {
+        self.tool_option(MassifTool, format!("--depth={:u}", depth))
+    }
+
+    /// Sets Massif's `--threshold=`, in percent.
+    pub fn threshold<'a>(&'a mut self, percent: f64) -> &'a mut ValgrindCommand {
+        self.tool_option(MassifTool, format!("--threshold={}", percent))
+    }
+
+    /// Sets Callgrind's `--callgrind-out-file=`.
+    pub fn callgrind_out_file<'a>(&'a mut self, path: &Path) -> &'a mut ValgrindCommand {
+        self.tool_option(CallgrindTool, format!("--callgrind-out-file={}", path.display()))
+    }
+
+    /// Sets Callgrind's `--dump-instr=`.
+    pub fn dump_instr<'a>(&'a mut self, dump_instr: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(CallgrindTool, format!("--dump-instr={}", yes_no(dump_instr)))
+    }
+
+    /// Sets Callgrind's `--collect-atstart=`.
+    pub fn collect_atstart<'a>(&'a mut self, collect_atstart: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(CallgrindTool, format!("--collect-atstart={}", yes_no(collect_atstart)))
+    }
+
+    /// Sets Callgrind's `--instr-atstart=`.
+    pub fn instr_atstart<'a>(&'a mut self, instr_atstart: bool) -> &'a mut ValgrindCommand {
+        self.tool_option(CallgrindTool, format!("--instr-atstart={}", yes_no(instr_atstart)))
+    }
+
+    /// Returns the arguments to pass to `valgrind`, writing XML output to `opt_xml_file`.
+    fn args_with_xml_file(&self, opt_xml_file: &Option<Path>) -> Result<Vec<String>, RunError> {
+        let mut args = vec![format!("--tool={}", self.tool.name())];
+        args.push(format!("--demangle={}", yes_no(self.demangle)));
+        args.push_all(self.core_options.as_slice());
+        if self.xml {
+            args.push(String::from_str("--xml=yes"));
+        }
+        match *opt_xml_file {
+            Some(ref xml_file) => args.push(format!("--xml-file={}", xml_file.display())),
+            None => (),
+        }
+        for &(ref tool, ref option) in self.tool_options.iter() {
+            if *tool != self.tool {
+                return Err(ToolMismatch(format!("the option {} only applies to {}, not {}",
+                                                option, tool.name(), self.tool.name())));
+            }
+            args.push(option.clone());
+        }
+        args.push(format!("{}", self.program.display()));
+        args.push_all(self.program_args.as_slice());
+        Ok(args)
+    }
+
+    /// The XML output file to pass to `valgrind`: the one that was set, if XML output is enabled.
+    fn xml_file(&self) -> Option<Path> {
+        if self.xml { self.opt_xml_file.clone() } else { None }
+    }
+
+    /// Returns the arguments to pass to `valgrind`. These are the arguments that `run()` uses,
+    /// except that `run()` adds a temporary XML output file if XML output is enabled without one.
+    pub fn get_args(&self) -> Result<Vec<String>, RunError> {
+        self.args_with_xml_file(&self.xml_file())
+    }
+
+    /// Runs the program under Valgrind, waits for it to exit and parses the XML output, if
+    /// enabled.
+    pub fn run(&self) -> Result<ValgrindOutput, RunError> {
+        let mut opt_temp_dir = None;
+        let opt_xml_file = match (self.xml, self.xml_file()) {
+                (false, _) => None,
+                (true, Some(xml_file)) => Some(xml_file),
+                (true, None) => {
+                    let temp_dir = try!(TempDir::new("valgrind").map_err(RunIoError));
+                    let xml_file = temp_dir.path().join("valgrind.xml");
+                    opt_temp_dir = Some(temp_dir);
+                    Some(xml_file)
+                },
+            };
+        let args = try!(self.args_with_xml_file(&opt_xml_file));
+        let opt_template = match opt_xml_file {
+                Some(ref xml_file) => {
+                    Some(try!(LogFileTemplate::new(format!("{}", xml_file.display()).as_slice()).map_err(InvalidTemplate)))
+                },
+                None => None,
+            };
+
+        let process = try!(Command::new(&self.valgrind).args(args.as_slice()).spawn().map_err(RunIoError));
+        // Valgrind keeps the process ID of the launcher, which is what `%p` expands to.
+        let pid = process.id() as uint;
+        let output = try!(process.wait_with_output().map_err(RunIoError));
+        let opt_report = match opt_template {
+                Some(ref template) => {
+                    let xml_file = Path::new(template.expand(pid, 0, |var| os::getenv(var)));
+                    let text = try!(File::open(&xml_file).read_to_string().map_err(RunIoError));
+                    Some(try!(parse_report(text.as_slice()).map_err(RunXmlError)))
+                },
+                None => None,
+            };
+        drop(opt_temp_dir);
+
+        Ok(ValgrindOutput {
+            status: output.status,
+            stdout: output.output,
+            stderr: output.error,
+            opt_report: opt_report,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{File, TempDir, UserRWX};
+    use std::io::fs;
+    use std::io::process::{ExitStatus};
+
+    use super::{ValgrindCommand};
+
+    /// A stub `valgrind` that writes a report with one error to the `--xml-file=`, expanding `%p`
+    /// like Valgrind, echoes its arguments and exits with status 3.
+    static STUB: &'static str = "#!/bin/sh
+for arg in \"$@\"; do
+    case \"$arg\" in
+        --xml-file=*) xml_file=$(echo \"${arg#--xml-file=}\" | sed \"s/%p/$$/g\") ;;
+    esac
+done
+if [ -n \"$xml_file\" ]; then
+    cat > \"$xml_file\" <<END
+<?xml version=\"1.0\"?>
+<valgrindoutput>
+<protocolversion>4</protocolversion>
+<pid>$$</pid>
+<tool>memcheck</tool>
+<error>
+  <unique>0x1</unique>
+  <kind>InvalidRead</kind>
+  <what>Invalid read of size 4</what>
+  <stack>
+    <frame><ip>0x400123</ip><obj>/tmp/prog</obj><fn>main</fn></frame>
+  </stack>
+</error>
+</valgrindoutput>
+END
+fi
+echo \"$@\"
+exit 3
+";
+
+    /// Writes the stub to `valgrind` in `dir`, returning its path.
+    fn install_stub(dir: &TempDir) -> Path {
+        let stub = dir.path().join("valgrind");
+        File::create(&stub).write_str(STUB).unwrap();
+        fs::chmod(&stub, UserRWX).unwrap();
+        stub
+    }
+
+    #[test]
+    fn test_run_with_stub() {
+        let dir = TempDir::new("valgrind-stub").unwrap();
+        let stub = install_stub(&dir);
+
+        let mut command = ValgrindCommand::new(Path::new("/tmp/prog"));
+        command.valgrind(stub).xml(true).xml_file(dir.path().join("vg.%p.xml")).arg("--flag");
+        let output = command.run().unwrap();
+        assert_eq!(output.status, ExitStatus(3));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.as_slice().contains("--xml=yes"));
+        assert!(stdout.as_slice().contains("--demangle=no"));
+        assert!(stdout.as_slice().ends_with("/tmp/prog --flag\n"));
+
+        let report = output.opt_report.unwrap();
+        assert_eq!(report.opt_tool, Some("memcheck".to_string()));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].what, "Invalid read of size 4".to_string());
+        assert_eq!(report.errors[0].stack[0].fn_name(), "main");
+
+        // Without XML output, neither the arguments nor the result mention an XML file.
+        command.xml(false);
+        assert!(!command.get_args().unwrap().iter().any(|arg| arg.as_slice().starts_with("--xml")));
+        let output = command.run().unwrap();
+        assert!(output.opt_report.is_none());
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Diagnostics explaining why a suppression does or does not match an error.
+
+use std::cmp::{max};
+use std::fmt::{FormatError, Formatter, Show};
+use std::iter::{range};
+use std::option::{Option};
+use std::result::{Result, fold_};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{Suppression};
+use matching::{CompiledFrame, CompiledWildcard, compile_frames, matches_frame};
+use report::{StackFrame, ValgrindError};
+
+/// How a single frame of a calling context was aligned with the stack trace.
+#[deriving(Clone, PartialEq, Show)]
+pub enum AlignmentStep {
+    /// The calling context frame at the first index matched the stack frame at the second index.
+    AlignedFrame(uint, uint),
+    /// The `...` calling context frame at the first index matched the stack frames from the
+    /// second index up to, but not including, the third index.
+    AlignedWildcard(uint, uint, uint),
+}
+
+/// The calling context frame at which matching failed.
+#[deriving(Clone, PartialEq, Show)]
+pub struct FrameMismatch {
+    /// The index of the calling context frame that failed to match.
+    pub frame_index: uint,
+    /// The index of the stack frame that the calling context frame failed to match, or `None` if
+    /// the stack trace had no more frames.
+    pub opt_stack_index: Option<uint>,
+}
+
+/// An explanation of whether, and why, a suppression matches an error.
+pub struct Explanation<'a> {
+    /// The explained suppression.
+    pub suppression: &'a Suppression,
+    /// The explained error.
+    pub error: &'a ValgrindError,
+    /// Whether the tool name and suppression type match the error.
+    pub type_matches: bool,
+    /// Whether the extra information of the suppression matches the error.
+    pub extra_info_matches: bool,
+    /// The alignment of the calling context with the stack trace. If the calling context does not
+    /// match, this is the alignment of the frames before the furthest mismatch that was reached.
+    pub alignment: Vec<AlignmentStep>,
+    /// The furthest mismatch that was reached, or `None` if the calling context matches.
+    pub opt_mismatch: Option<FrameMismatch>,
+}
+
+impl<'a> Explanation<'a> {
+    /// Returns whether the suppression matches the error.
+    pub fn is_match(&self) -> bool {
+        self.type_matches && self.extra_info_matches && self.opt_mismatch.is_none()
+    }
+}
+
+/// Explains whether, and why, `suppression` matches `error`.
+pub fn explain<'a>(suppression: &'a Suppression, error: &'a ValgrindError) -> Explanation<'a> {
+    let mut alignment = Vec::new();
+    let mut opt_furthest: Option<(FrameMismatch, Vec<AlignmentStep>)> = None;
+    let opt_mismatch = if align(compile_frames(suppression.frames.as_slice()).as_slice(), error.stack.as_slice(), 0, 0, &mut alignment, &mut opt_furthest) {
+            None
+        } else {
+            match opt_furthest {
+                None => None,
+                Some((mismatch, furthest_alignment)) => {
+                    alignment = furthest_alignment;
+                    Some(mismatch)
+                },
+            }
+        };
+    Explanation {
+        suppression: suppression,
+        error: error,
+        type_matches: suppression.type_ == error.type_,
+        extra_info_matches: suppression.matches_extra_info(error),
+        alignment: alignment,
+        opt_mismatch: opt_mismatch,
+    }
+}
+
+/// Aligns `frames` from `frame_index` with `stack` from `stack_index`, backtracking over the
+/// stack frames matched by each `...`. Returns whether the alignment succeeded, in which case
+/// `path` holds the complete alignment. Otherwise, `opt_furthest` holds the mismatch at the
+/// furthest calling context frame that was reached.
+fn align(frames: &[CompiledFrame], stack: &[StackFrame], frame_index: uint, stack_index: uint,
+         path: &mut Vec<AlignmentStep>, opt_furthest: &mut Option<(FrameMismatch, Vec<AlignmentStep>)>) -> bool {
+    if frame_index == frames.len() {
+        return true;
+    }
+    match frames[frame_index] {
+        CompiledWildcard => {
+            for end in range(stack_index, stack.len() + 1) {
+                path.push(AlignedWildcard(frame_index, stack_index, end));
+                if align(frames, stack, frame_index + 1, end, path, opt_furthest) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        },
+        ref frame => {
+            if stack_index < stack.len() && matches_frame(frame, &stack[stack_index]) {
+                path.push(AlignedFrame(frame_index, stack_index));
+                if align(frames, stack, frame_index + 1, stack_index + 1, path, opt_furthest) {
+                    return true;
+                }
+                path.pop();
+                return false;
+            }
+
+            let is_further = match *opt_furthest {
+                    None => true,
+                    Some((ref furthest, _)) => frame_index > furthest.frame_index,
+                };
+            if is_further {
+                *opt_furthest = Some((FrameMismatch {
+                    frame_index: frame_index,
+                    opt_stack_index: if stack_index < stack.len() { Some(stack_index) } else { None },
+                }, path.clone()));
+            }
+            false
+        },
+    }
+}
+
+/// Pads `s` with spaces on the right to a width of `width` characters.
+fn pad(s: &str, width: uint) -> String {
+    let mut padded = s.to_string();
+    for _ in range(s.char_len(), width) {
+        padded.push_char(' ');
+    }
+    padded
+}
+
+fn ok_or_mismatch(is_match: bool) -> &'static str {
+    if is_match { "ok" } else { "MISMATCH" }
+}
+
+impl<'a> Show for Explanation<'a> {
+    /// Renders the explanation as a table with the calling context of the suppression on the left
+    /// and the aligned stack frames of the error on the right.
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        let mut rows: Vec<(String, String, &'static str)> = Vec::new();
+        for step in self.alignment.iter() {
+            match *step {
+                AlignedFrame(frame_index, stack_index) => {
+                    rows.push((format!("{}", self.suppression.frames[frame_index]),
+                               format!("#{:u} {}", stack_index, self.error.stack[stack_index]),
+                               "ok"));
+                },
+                AlignedWildcard(frame_index, start, end) => {
+                    if start == end {
+                        rows.push((format!("{}", self.suppression.frames[frame_index]), String::new(), "ok"));
+                    }
+                    for stack_index in range(start, end) {
+                        let frame = if stack_index == start {
+                                format!("{}", self.suppression.frames[frame_index])
+                            } else {
+                                String::new()
+                            };
+                        rows.push((frame, format!("#{:u} {}", stack_index, self.error.stack[stack_index]), "ok"));
+                    }
+                },
+            }
+        }
+        match self.opt_mismatch {
+            None => (),
+            Some(ref mismatch) => {
+                let stack_frame = match mismatch.opt_stack_index {
+                        None => String::from_str("(end of stack trace)"),
+                        Some(stack_index) => format!("#{:u} {}", stack_index, self.error.stack[stack_index]),
+                    };
+                rows.push((format!("{}", self.suppression.frames[mismatch.frame_index]), stack_frame, "MISMATCH"));
+                for frame in self.suppression.frames.slice_from(mismatch.frame_index + 1).iter() {
+                    rows.push((format!("{}", frame), String::new(), "not reached"));
+                }
+            },
+        }
+
+        let frames_width = rows.iter().fold("suppression".len(), |width, &(ref frame, _, _)| max(width, frame.as_slice().char_len()));
+        let stack_width = rows.iter().fold("error".len(), |width, &(_, ref stack_frame, _)| max(width, stack_frame.as_slice().char_len()));
+
+        (writeln!(fmt, "suppression '{}' {} the error", self.suppression.name, if self.is_match() { "matches" } else { "does not match" }))
+            .and_then(|()| -> Result<(), FormatError> {
+                writeln!(fmt, "  type:       {} vs {}: {}", self.suppression.type_, self.error.type_, ok_or_mismatch(self.type_matches))
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                writeln!(fmt, "  extra info: {}", ok_or_mismatch(self.extra_info_matches))
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                writeln!(fmt, "  {} | {} |", pad("suppression", frames_width), pad("error", stack_width))
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                fold_(rows.iter().map(|&(ref frame, ref stack_frame, status)| -> Result<(), FormatError> {
+                    writeln!(fmt, "  {} | {} | {}", pad(frame.as_slice(), frames_width), pad(stack_frame.as_slice(), stack_width), status)
+                }))
+            })
+    }
+}
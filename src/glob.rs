@@ -0,0 +1,317 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Globs with the semantics of the `obj:` and `fun:` frames of Valgrind suppressions.
+//!
+//! Valgrind matches globs byte by byte with `VG_(string_match)`:
+//!
+//! * `*` matches any sequence of bytes, including the empty sequence.
+//! * `?` matches exactly one byte.
+//! * `\` matches the following byte literally, so `\*` only matches `*`. A trailing `\` never
+//!   matches anything.
+//! * Every other byte only matches itself. In particular, characters such as `<`, `>`, `:`, `(`,
+//!   `)`, `[`, `]` and `,` are not special, and neither `*` nor `?` treat `/` specially. For
+//!   example, `*/libfoo.so*` matches `/usr/lib/libfoo.so.1`.
+//!
+//! The whole name must match, so `malloc` does not match `malloc_trim` while `malloc*` does.
+//!
+//! `fun:` globs are matched against mangled function names, as printed with `--demangle=no`. For
+//! example, `_ZNSt6vectorIiSaIiEE9push_back*` matches `_ZNSt6vectorIiSaIiEE9push_backERKi`
+//! (`std::vector<int>::push_back(int const&)`), while `std::vector<*>::push_back` matches no
+//! function of a C++ program.
+//!
+//! # See also
+//! * [Suppressing errors](http://valgrind.org/docs/manual/manual-core.html#manual-core.suppress). Valgrind User Manual.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::iter::{range};
+use std::option::{Option};
+use std::result::{Result};
+use std::str;
+use std::string::{String};
+use std::vec::{Vec};
+
+#[deriving(Clone, PartialEq)]
+enum GlobToken {
+    /// A sequence of bytes that must match literally.
+    LiteralToken(Vec<u8>),
+    /// `*`
+    AnySequenceToken,
+    /// `?`
+    AnyByteToken,
+    /// A trailing `\`, which never matches.
+    UnmatchableToken,
+}
+
+/// A compiled glob of a suppression frame.
+#[deriving(Clone, PartialEq)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<GlobToken>,
+}
+
+impl Glob {
+
+    /// Compiles `pattern`. Every pattern is valid; a pattern ending in an unescaped `\` is
+    /// compiled to a glob that never matches, as in Valgrind.
+    pub fn new(pattern: &str) -> Glob {
+        let mut tokens = Vec::new();
+        let mut literal: Vec<u8> = Vec::new();
+        let mut bytes = pattern.bytes();
+        loop {
+            let b = match bytes.next() {
+                    None => break,
+                    Some(b) => b,
+                };
+            let token = match b {
+                    b'*' => AnySequenceToken,
+                    b'?' => AnyByteToken,
+                    b'\\' => {
+                        match bytes.next() {
+                            None => UnmatchableToken,
+                            Some(escaped) => {
+                                literal.push(escaped);
+                                continue;
+                            },
+                        }
+                    },
+                    _ => {
+                        literal.push(b);
+                        continue;
+                    },
+                };
+            if !literal.is_empty() {
+                tokens.push(LiteralToken(literal));
+                literal = Vec::new();
+            }
+            // Consecutive `*` match the same strings as a single `*`.
+            if token == AnySequenceToken && tokens.last() == Some(&AnySequenceToken) {
+                continue;
+            }
+            tokens.push(token);
+        }
+        if !literal.is_empty() {
+            tokens.push(LiteralToken(literal));
+        }
+
+        Glob {
+            pattern: pattern.to_string(),
+            tokens: tokens,
+        }
+    }
+
+    /// The pattern from which this glob was compiled.
+    pub fn as_str<'a>(&'a self) -> &'a str {
+        self.pattern.as_slice()
+    }
+
+    /// Returns whether this glob matches the whole of `s`.
+    pub fn matches(&self, s: &str) -> bool {
+        match_tokens(self.tokens.as_slice(), s.as_bytes())
+    }
+
+    /// Returns whether this glob has no wildcards, and hence matches exactly one string.
+    pub fn is_literal(&self) -> bool {
+        match self.tokens.as_slice() {
+            [] | [LiteralToken(_)] => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the literal prefix that every string matched by this glob starts with. This is
+    /// useful for indexing globs, e.g. in a sorted map or a trie.
+    pub fn literal_prefix(&self) -> String {
+        match self.tokens.head() {
+            Some(&LiteralToken(ref literal)) => String::from_utf8_lossy(literal.as_slice()).into_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Converts this glob to an equivalent, anchored regular expression. Each `?` is converted to
+    /// `.`, which matches a single character rather than a single byte, so the two only agree on
+    /// ASCII text. Only regular expression metacharacters are escaped, and a literal that is not
+    /// UTF-8 has its non-ASCII bytes escaped as `\xNN`.
+    pub fn to_regex(&self) -> String {
+        let mut regex = String::from_str("(?s)^");
+        for token in self.tokens.iter() {
+            match *token {
+                LiteralToken(ref literal) => {
+                    match str::from_utf8(literal.as_slice()) {
+                        Some(text) => {
+                            for c in text.chars() {
+                                push_regex_char(&mut regex, c);
+                            }
+                        },
+                        None => {
+                            for &b in literal.iter() {
+                                if b < 0x80 {
+                                    push_regex_char(&mut regex, b as char);
+                                } else {
+                                    regex.push_str(format!("\\x{:02X}", b).as_slice());
+                                }
+                            }
+                        },
+                    }
+                },
+                AnySequenceToken => regex.push_str(".*"),
+                AnyByteToken => regex.push_char('.'),
+                UnmatchableToken => regex.push_str("[^\\s\\S]"),
+            }
+        }
+        regex.push_char('$');
+        regex
+    }
+}
+
+impl Show for Glob {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}", self.pattern.as_slice())
+    }
+}
+
+/// Appends `c` to `regex`, escaping it if it is a regular expression metacharacter.
+fn push_regex_char(regex: &mut String, c: char) {
+    if "\\.+*?()|[]{}^$#&-~".contains_char(c) {
+        regex.push_char('\\');
+    }
+    regex.push_char(c);
+}
+
+fn match_tokens(tokens: &[GlobToken], s: &[u8]) -> bool {
+    match tokens.head() {
+        None => s.is_empty(),
+        Some(&LiteralToken(ref literal)) => {
+            s.starts_with(literal.as_slice()) && match_tokens(tokens.tail(), s.slice_from(literal.len()))
+        },
+        Some(&AnySequenceToken) => {
+            // A trailing `*` matches whatever remains.
+            tokens.len() == 1 || range(0, s.len() + 1).any(|skip| match_tokens(tokens.tail(), s.slice_from(skip)))
+        },
+        Some(&AnyByteToken) => {
+            !s.is_empty() && match_tokens(tokens.tail(), s.tail())
+        },
+        Some(&UnmatchableToken) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Glob, LiteralToken};
+
+    fn matches(pattern: &str, s: &str) -> bool {
+        Glob::new(pattern).matches(s)
+    }
+
+    #[test]
+    fn test_literal() {
+        assert!(matches("malloc", "malloc"));
+        assert!(!matches("malloc", "malloc_trim"));
+        assert!(!matches("malloc", "xmalloc"));
+        assert!(!matches("malloc", ""));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn test_any_sequence() {
+        assert!(matches("malloc*", "malloc"));
+        assert!(matches("malloc*", "malloc_trim"));
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("*alloc", "calloc"));
+        assert!(matches("*alloc", "alloc"));
+        assert!(!matches("*alloc", "allocate"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXcYYb"));
+        assert!(matches("a**b", "ab"));
+    }
+
+    #[test]
+    fn test_any_byte() {
+        assert!(matches("?", "a"));
+        assert!(!matches("?", ""));
+        assert!(!matches("?", "ab"));
+        assert!(matches("m?lloc", "malloc"));
+        assert!(!matches("m?lloc", "mlloc"));
+        assert!(matches("*?", "a"));
+        assert!(!matches("*?", ""));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("\\?", "?"));
+        assert!(!matches("\\?", "a"));
+        assert!(matches("a\\\\b", "a\\b"));
+        assert!(matches("\\a", "a"));
+        assert!(!matches("a\\", "a"));
+        assert!(!matches("a\\", "a\\"));
+    }
+
+    #[test]
+    fn test_special_characters_are_literal() {
+        assert!(matches("std::vector<*>::push_back", "std::vector<int, std::allocator<int> >::push_back"));
+        assert!(matches("*/libfoo.so*", "/usr/lib/libfoo.so.1"));
+        assert!(matches("foo(int)", "foo(int)"));
+        assert!(matches("[a]", "[a]"));
+        assert!(!matches("[a]", "a"));
+        assert!(matches("core::*", "core::fmt::write"));
+        assert!(matches("*::drop", "alloc::arc::Arc<T>::drop"));
+    }
+
+    #[test]
+    fn test_ellipsis_is_literal() {
+        // `...` is only special as a whole calling context line, not within a glob.
+        assert!(matches("...", "..."));
+        assert!(!matches("...", "foo"));
+        assert!(matches("foo...", "foo..."));
+        assert!(!matches("foo...", "foobar"));
+    }
+
+    #[test]
+    fn test_is_literal() {
+        assert!(Glob::new("malloc").is_literal());
+        assert!(Glob::new("").is_literal());
+        assert!(Glob::new("a\\*b").is_literal());
+        assert!(!Glob::new("malloc*").is_literal());
+        assert!(!Glob::new("m?lloc").is_literal());
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(Glob::new("malloc").literal_prefix(), "malloc".to_string());
+        assert_eq!(Glob::new("std::vector<*>::push_back").literal_prefix(), "std::vector<".to_string());
+        assert_eq!(Glob::new("a\\*b*").literal_prefix(), "a*b".to_string());
+        assert_eq!(Glob::new("*alloc").literal_prefix(), "".to_string());
+        assert_eq!(Glob::new("?alloc").literal_prefix(), "".to_string());
+    }
+
+    #[test]
+    fn test_to_regex() {
+        assert_eq!(Glob::new("malloc*").to_regex(), "(?s)^malloc.*$".to_string());
+        assert_eq!(Glob::new("m?lloc").to_regex(), "(?s)^m.lloc$".to_string());
+        assert_eq!(Glob::new("a::b").to_regex(), "(?s)^a::b$".to_string());
+        assert_eq!(Glob::new("a\\*").to_regex(), "(?s)^a\\*$".to_string());
+        assert_eq!(Glob::new("*<impl Foo>::bar*").to_regex(), "(?s)^.*<impl Foo>::bar.*$".to_string());
+        assert_eq!(Glob::new("foo(int).[0]").to_regex(), "(?s)^foo\\(int\\)\\.\\[0\\]$".to_string());
+        assert_eq!(Glob::new("a-b~c&d#e").to_regex(), "(?s)^a\\-b\\~c\\&d\\#e$".to_string());
+        assert_eq!(Glob::new("é").to_regex(), "(?s)^é$".to_string());
+
+        let glob = Glob {
+            pattern: String::new(),
+            tokens: vec!(LiteralToken(vec!(b'a', 0xff, b'.'))),
+        };
+        assert_eq!(glob.to_regex(), "(?s)^a\\xFF\\.$".to_string());
+    }
+}
@@ -0,0 +1,826 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Helgrind errors: data races, lock misuse and lock order violations.
+//!
+//! # See also
+//! * [Helgrind: a thread error detector](http://valgrind.org/docs/manual/hg-manual.html). Valgrind User Manual.
+
+use std::collections::{HashMap};
+use std::fmt::{FormatError, Formatter, Show};
+use std::from_str::{from_str};
+use std::mem;
+use std::num::{from_str_radix};
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{Suppression, SuppressionType};
+use builder::{BuildError};
+use log::{parse_stack_line, split_prefix};
+use report::{AuxStack, StackFrame, ValgrindError};
+use xml::{Element, XmlError, parse_document, stack_frame_from_xml};
+
+/// The kind of a Helgrind error, which is also the kind of the `Helgrind:` suppressions that
+/// suppress it.
+#[deriving(Clone, PartialEq, Eq)]
+pub enum HelgrindErrorKind {
+    /// A possible data race.
+    RaceError,
+    /// A thread unlocked a lock that was not locked.
+    UnlockUnlockedError,
+    /// A thread unlocked a lock held by another thread.
+    UnlockForeignError,
+    /// A thread unlocked something that is not a lock.
+    UnlockBogusError,
+    /// A call to a pthread function failed.
+    PthApiError,
+    /// A lock order violation.
+    LockOrderError,
+    /// Dubious use of a condition variable.
+    DubiousError,
+    /// Any other misuse of the pthread API.
+    MiscError,
+}
+
+impl HelgrindErrorKind {
+    /// Parses the kind of an error as named in the XML output and in suppressions (e.g. `Race`).
+    pub fn from_name(name: &str) -> Option<HelgrindErrorKind> {
+        match name {
+            "Race" => Some(RaceError),
+            "UnlockUnlocked" => Some(UnlockUnlockedError),
+            "UnlockForeign" => Some(UnlockForeignError),
+            "UnlockBogus" => Some(UnlockBogusError),
+            "PthAPIerror" => Some(PthApiError),
+            "LockOrder" => Some(LockOrderError),
+            "Dubious" => Some(DubiousError),
+            "Misc" => Some(MiscError),
+            _ => None,
+        }
+    }
+
+    /// The name of the kind as used in the XML output and in suppressions.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RaceError => "Race",
+            UnlockUnlockedError => "UnlockUnlocked",
+            UnlockForeignError => "UnlockForeign",
+            UnlockBogusError => "UnlockBogus",
+            PthApiError => "PthAPIerror",
+            LockOrderError => "LockOrder",
+            DubiousError => "Dubious",
+            MiscError => "Misc",
+        }
+    }
+
+    /// Returns the kind of an error from the description in its plain-text header.
+    fn from_header(what: &str) -> HelgrindErrorKind {
+        if what.starts_with("Possible data race") {
+            RaceError
+        } else if what.contains("unlocked a not-locked lock") {
+            UnlockUnlockedError
+        } else if what.contains("currently held by thread") {
+            UnlockForeignError
+        } else if what.contains("unlocked an invalid lock") {
+            UnlockBogusError
+        } else if what.contains("'s call to ") && what.contains(" failed") {
+            PthApiError
+        } else if what.contains(": lock order \"") {
+            LockOrderError
+        } else if what.contains("dubious") {
+            DubiousError
+        } else {
+            MiscError
+        }
+    }
+}
+
+impl Show for HelgrindErrorKind {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+/// The announcement of a thread, which identifies the thread numbers used in errors.
+#[deriving(Clone)]
+pub struct ThreadAnnouncement {
+    /// The Helgrind thread number.
+    pub thread_id: uint,
+    /// Whether the thread is the root thread of the program.
+    pub is_root: bool,
+    /// The stack trace of the creation of the thread. This is empty for the root thread.
+    pub stack: Vec<StackFrame>,
+}
+
+/// The announcement of a lock, with where it was first observed.
+#[deriving(Clone)]
+pub struct LockAnnouncement {
+    /// The Helgrind lock number (`<hlockid>`), if the announcement was read from XML output.
+    pub opt_lock_id: Option<uint>,
+    /// The address of the lock, if the announcement gives it.
+    pub opt_address: Option<u64>,
+    /// The stack trace at which the lock was first observed.
+    pub stack: Vec<StackFrame>,
+}
+
+/// The kind of a memory access.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum AccessKind {
+    ReadAccess,
+    WriteAccess,
+}
+
+/// One of the memory accesses of a data race.
+#[deriving(Clone)]
+pub struct Access {
+    /// Whether the access was a read or a write.
+    pub kind: AccessKind,
+    /// The size of the access in bytes.
+    pub size: uint,
+    /// The accessed address, if given.
+    pub opt_address: Option<u64>,
+    /// The thread number of the accessing thread, if given.
+    pub opt_thread_id: Option<uint>,
+    /// The addresses of the locks held by the accessing thread.
+    pub locks_held: Vec<u64>,
+    /// The stack trace of the access, innermost frame first.
+    pub stack: Vec<StackFrame>,
+}
+
+/// The acquisition of a lock, as shown in a lock order violation.
+#[deriving(Clone)]
+pub struct LockAcquisition {
+    /// The address of the lock.
+    pub lock: u64,
+    /// The stack trace of the acquisition.
+    pub stack: Vec<StackFrame>,
+}
+
+/// The details of a lock order violation.
+#[deriving(Clone)]
+pub struct LockOrder {
+    /// The lock that is required to be acquired first.
+    pub before: u64,
+    /// The lock that is required to be acquired second.
+    pub after: u64,
+    /// The acquisitions in the observed (incorrect) order.
+    pub observed: Vec<LockAcquisition>,
+    /// The acquisitions that established the required order.
+    pub required: Vec<LockAcquisition>,
+}
+
+/// A single error reported by Helgrind.
+#[deriving(Clone)]
+pub struct HelgrindError {
+    /// The kind of the error.
+    pub kind: HelgrindErrorKind,
+    /// The description of the error.
+    pub what: String,
+    /// The thread number of the thread that caused the error, if known.
+    pub opt_thread_id: Option<uint>,
+    /// The stack trace of the error, innermost frame first.
+    pub stack: Vec<StackFrame>,
+    /// For a data race, the access that was detected.
+    pub opt_access: Option<Access>,
+    /// For a data race, the earlier access that it conflicts with, if known.
+    pub opt_conflict: Option<Access>,
+    /// For a lock order violation, its details.
+    pub opt_lock_order: Option<LockOrder>,
+    /// Any other auxiliary descriptions, e.g. "Address 0x601038 is 0 bytes inside data symbol".
+    pub aux: Vec<AuxStack>,
+}
+
+/// The threads, locks and errors that Helgrind reported for a single process.
+#[deriving(Clone)]
+pub struct HelgrindReport {
+    /// The process ID of the process, if known.
+    pub opt_pid: Option<uint>,
+    /// The announced threads, in the order of the announcements.
+    pub threads: Vec<ThreadAnnouncement>,
+    /// The announced locks, in the order of the announcements.
+    pub locks: Vec<LockAnnouncement>,
+    /// The errors, in the order that they were reported.
+    pub errors: Vec<HelgrindError>,
+}
+
+impl HelgrindReport {
+    fn new(opt_pid: Option<uint>) -> HelgrindReport {
+        HelgrindReport {
+            opt_pid: opt_pid,
+            threads: Vec::new(),
+            locks: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Returns the announcement of the thread numbered `thread_id`.
+    pub fn thread<'a>(&'a self, thread_id: uint) -> Option<&'a ThreadAnnouncement> {
+        self.threads.iter().find(|thread| thread.thread_id == thread_id)
+    }
+
+    /// Returns the announcement of the lock at `address`.
+    pub fn lock<'a>(&'a self, address: u64) -> Option<&'a LockAnnouncement> {
+        self.locks.iter().find(|lock| lock.opt_address == Some(address))
+    }
+
+    /// Returns the announcement of the lock numbered `lock_id` in XML output.
+    pub fn lock_by_id<'a>(&'a self, lock_id: uint) -> Option<&'a LockAnnouncement> {
+        self.locks.iter().find(|lock| lock.opt_lock_id == Some(lock_id))
+    }
+}
+
+impl HelgrindError {
+
+    /// The type of the suppressions that suppress this error.
+    pub fn suppression_type(&self) -> SuppressionType {
+        SuppressionType::from_parts("Helgrind", self.kind.name())
+    }
+
+    /// Converts this error to a generic error, so that it can be matched against suppressions.
+    pub fn to_error(&self) -> ValgrindError {
+        ValgrindError {
+            opt_unique: None,
+            type_: self.suppression_type(),
+            opt_extra: None,
+            opt_leak_kind: None,
+            opt_leaked: None,
+            what: self.what.clone(),
+            stack: self.stack.clone(),
+            aux: self.aux.clone(),
+            opt_suppressed_by: None,
+        }
+    }
+
+    /// Generates a suppression named `name` for this error, like `--gen-suppressions=yes`: each
+    /// stack frame becomes a `fun:` frame, or an `obj:` frame if the function is unknown.
+    ///
+    /// Valgrind matches `fun:` frames against mangled names, so the report must come from output
+    /// generated with `--demangle=no` for the suppression to match C++ or Rust functions.
+    pub fn suppression(&self, name: &str) -> Result<Suppression, BuildError> {
+        let mut builder = Suppression::builder(name).tool_type("Helgrind", self.kind.name());
+        for frame in self.stack.iter() {
+            builder = match frame.opt_fn_name {
+                    Some(ref fn_name) => builder.fun(fn_name.as_slice()),
+                    None => builder.obj(frame.obj_name()),
+                };
+        }
+        builder.build()
+    }
+}
+
+/// Parses a hexadecimal number such as `0x601038`.
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s.trim_chars(&['"', ',', '.']);
+    if s.starts_with("0x") {
+        from_str_radix::<u64>(s.slice_from(2), 16)
+    } else {
+        None
+    }
+}
+
+/// Returns the number following `#` in `what`, e.g. the thread number in "by thread #2".
+fn thread_number(what: &str) -> Option<uint> {
+    what.find('#').and_then(|pos| {
+        let rest = what.slice_from(pos + 1);
+        let end = rest.find(|c: char| !c.is_digit()).unwrap_or(rest.len());
+        from_str(rest.slice_to(end))
+    })
+}
+
+/// Returns the hexadecimal numbers in `s`, in order.
+fn hex_numbers(s: &str) -> Vec<u64> {
+    s.words().filter_map(parse_hex).collect()
+}
+
+/// Parses an access description, e.g. "Possible data race during write of size 4 at 0x601038 by
+/// thread #2" or "This conflicts with a previous read of size 4 by thread #1".
+fn parse_access(what: &str) -> Option<Access> {
+    let kind = if what.contains(" read of size ") {
+            ReadAccess
+        } else if what.contains(" write of size ") {
+            WriteAccess
+        } else {
+            return None;
+        };
+    let words: Vec<&str> = what.words().collect();
+    let size = words.iter().position(|word| *word == "size").and_then(|i| {
+            if i + 1 < words.len() { from_str(words[i + 1]) } else { None }
+        });
+    let opt_address = words.iter().position(|word| *word == "at").and_then(|i| {
+            if i + 1 < words.len() { parse_hex(words[i + 1]) } else { None }
+        });
+    Some(Access {
+        kind: kind,
+        size: size.unwrap_or(0),
+        opt_address: opt_address,
+        opt_thread_id: thread_number(what.slice_from(what.find_str(" by ").unwrap_or(0))),
+        locks_held: Vec::new(),
+        stack: Vec::new(),
+    })
+}
+
+/// Parses a "Locks held: ..." line, returning the addresses of the locks.
+fn parse_locks_held(line: &str) -> Option<Vec<u64>> {
+    if line.starts_with("Locks held: ") {
+        Some(hex_numbers(line.slice_from(12)))
+    } else {
+        None
+    }
+}
+
+/// Parses the lock addresses of a "lock order \"0x601040 before 0x601080\" violated" header.
+fn parse_lock_order(what: &str) -> Option<LockOrder> {
+    match hex_numbers(what).as_slice() {
+        [before, after, ..] => {
+            Some(LockOrder {
+                before: before,
+                after: after,
+                observed: Vec::new(),
+                required: Vec::new(),
+            })
+        },
+        _ => None,
+    }
+}
+
+/// Converts the `<stack>` element `stack` to a stack trace.
+fn stack_from_xml(stack: &Element) -> Vec<StackFrame> {
+    stack.children_named("frame").iter().map(|frame| stack_frame_from_xml(*frame)).collect()
+}
+
+/// Returns the text of an `<auxwhat>` element, or of the `<text>` of an `<xauxwhat>` element.
+fn aux_text(element: &Element) -> String {
+    if element.name.as_slice() == "xauxwhat" {
+        element.child_text("text").unwrap_or(String::new())
+    } else {
+        element.text()
+    }
+}
+
+/// Converts an `<error>` element of the XML output of Helgrind to an error.
+pub fn error_from_xml(error: &Element) -> HelgrindError {
+    let kind = error.child_text("kind").and_then(|kind| HelgrindErrorKind::from_name(kind.as_slice())).unwrap_or(MiscError);
+    let what = error.child_text("what")
+        .or_else(|| error.child("xwhat").and_then(|xwhat| xwhat.child_text("text")))
+        .unwrap_or(String::new());
+    let mut result = HelgrindError {
+        kind: kind.clone(),
+        what: what.clone(),
+        opt_thread_id: error.child("xwhat").and_then(|xwhat| xwhat.child_text("hthreadid"))
+            .and_then(|id| from_str(id.as_slice()))
+            .or_else(|| thread_number(what.as_slice())),
+        stack: Vec::new(),
+        opt_access: if kind == RaceError { parse_access(what.as_slice()) } else { None },
+        opt_conflict: None,
+        opt_lock_order: if kind == LockOrderError { parse_lock_order(what.as_slice()) } else { None },
+        aux: Vec::new(),
+    };
+
+    // The stacks follow the descriptions that they belong to.
+    let mut opt_last_aux: Option<String> = None;
+    let mut seen_stack = false;
+    for child in error.elements() {
+        match child.name.as_slice() {
+            "auxwhat" | "xauxwhat" => {
+                let text = aux_text(child);
+                match parse_locks_held(text.as_slice()) {
+                    Some(locks) => {
+                        let access = if result.opt_conflict.is_some() { &mut result.opt_conflict } else { &mut result.opt_access };
+                        match *access {
+                            Some(ref mut access) => access.locks_held = locks,
+                            None => (),
+                        }
+                    },
+                    None => opt_last_aux = Some(text),
+                }
+            },
+            "stack" => {
+                let stack = stack_from_xml(child);
+                if !seen_stack {
+                    seen_stack = true;
+                    result.stack = stack.clone();
+                    match result.opt_access {
+                        Some(ref mut access) => access.stack = stack,
+                        None => (),
+                    }
+                } else {
+                    let aux = opt_last_aux.take().unwrap_or(String::new());
+                    attach_aux(&mut result, aux, stack);
+                }
+            },
+            _ => (),
+        }
+    }
+    match opt_last_aux {
+        Some(aux) => attach_aux(&mut result, aux, Vec::new()),
+        None => (),
+    }
+    result
+}
+
+/// Attaches the auxiliary description `what` and its stack trace to `error`.
+fn attach_aux(error: &mut HelgrindError, what: String, stack: Vec<StackFrame>) {
+    if what.as_slice().starts_with("This conflicts with a previous") {
+        error.opt_conflict = parse_access(what.as_slice()).map(|mut access| {
+            access.stack = stack.clone();
+            access
+        });
+        return;
+    }
+    match error.opt_lock_order {
+        Some(ref mut lock_order) => {
+            let what = what.as_slice().trim();
+            let opt_lock = what.words().last().and_then(parse_hex);
+            match opt_lock {
+                Some(lock) if what.contains("acquisition of lock at") => {
+                    let acquisition = LockAcquisition {
+                        lock: lock,
+                        stack: stack,
+                    };
+                    if what.starts_with("Required order") || (what.starts_with("followed by") && !lock_order.required.is_empty()) {
+                        lock_order.required.push(acquisition);
+                    } else {
+                        lock_order.observed.push(acquisition);
+                    }
+                    return;
+                },
+                _ => (),
+            }
+        },
+        None => (),
+    }
+    error.aux.push(AuxStack {
+        what: what,
+        stack: stack,
+    });
+}
+
+/// Converts the root `<valgrindoutput>` element of the XML output of Helgrind to a report.
+pub fn report_from_xml(root: &Element) -> HelgrindReport {
+    let mut report = HelgrindReport::new(root.child_text("pid").and_then(|pid| from_str(pid.as_slice())));
+    for child in root.elements() {
+        match child.name.as_slice() {
+            "announcethread" => {
+                let stack = child.child("stack").map(stack_from_xml).unwrap_or(Vec::new());
+                report.threads.push(ThreadAnnouncement {
+                    thread_id: child.child_text("hthreadid").and_then(|id| from_str(id.as_slice())).unwrap_or(0),
+                    is_root: stack.is_empty(),
+                    stack: stack,
+                });
+            },
+            "announcelock" => {
+                // `<hlockid>` is a decimal lock number; the address, if any, is in the
+                // description of the lock.
+                match child.child_text("hlockid").and_then(|id| from_str::<uint>(id.as_slice())) {
+                    Some(lock_id) => {
+                        let opt_address = child.elements()
+                            .filter(|element| element.name.as_slice() != "hlockid" && element.name.as_slice() != "stack")
+                            .filter_map(|element| hex_numbers(element.text().as_slice()).move_iter().next())
+                            .next();
+                        report.locks.push(LockAnnouncement {
+                            opt_lock_id: Some(lock_id),
+                            opt_address: opt_address,
+                            stack: child.child("stack").map(stack_from_xml).unwrap_or(Vec::new()),
+                        });
+                    },
+                    None => (),
+                }
+            },
+            "error" => report.errors.push(error_from_xml(child)),
+            _ => (),
+        }
+    }
+    report
+}
+
+/// Parses the XML output of Helgrind into a report.
+pub fn parse_xml(text: &str) -> Result<HelgrindReport, XmlError> {
+    let root = try!(parse_document(text));
+    Ok(report_from_xml(&root))
+}
+
+/// Parses a block of plain-text output between two separator lines into `report`. `paragraphs`
+/// holds the lines of the block, split at blank lines.
+fn parse_text_block(report: &mut HelgrindReport, paragraphs: &[Vec<String>]) {
+    let head = match paragraphs.head().and_then(|paragraph| paragraph.as_slice().head()) {
+            None => return,
+            Some(head) => head.as_slice(),
+        };
+    let stack_of = |paragraph: &Vec<String>| -> Vec<StackFrame> {
+        paragraph.iter().filter_map(|line| parse_stack_line(line.as_slice())).collect()
+    };
+
+    if head.starts_with("Thread #") && (head.ends_with(" was created") || head.ends_with("root thread")) {
+        report.threads.push(ThreadAnnouncement {
+            thread_id: thread_number(head).unwrap_or(0),
+            is_root: head.ends_with("root thread"),
+            stack: stack_of(&paragraphs[0]),
+        });
+        return;
+    }
+    if head.starts_with("Lock at ") {
+        // The locks held by the threads of an error are announced, one per paragraph, in the same
+        // block as the error that follows them.
+        let announced = paragraphs.iter().take_while(|paragraph| {
+                paragraph.as_slice().head().map_or(false, |line| line.as_slice().starts_with("Lock at "))
+            }).count();
+        for paragraph in paragraphs.slice_to(announced).iter() {
+            match paragraph.as_slice().head().and_then(|line| line.as_slice().words().nth(2)).and_then(parse_hex) {
+                Some(address) => {
+                    report.locks.push(LockAnnouncement {
+                        opt_lock_id: None,
+                        opt_address: Some(address),
+                        stack: stack_of(paragraph),
+                    });
+                },
+                None => (),
+            }
+        }
+        parse_text_block(report, paragraphs.slice_from(announced));
+        return;
+    }
+
+    let first = &paragraphs[0];
+    let kind = HelgrindErrorKind::from_header(head);
+    // The header of a lock order violation is a paragraph of its own; its stack is that of the
+    // observed acquisition in the next paragraph.
+    let stack = if kind == LockOrderError {
+            paragraphs.iter().map(|paragraph| stack_of(paragraph)).find(|stack| !stack.is_empty()).unwrap_or(Vec::new())
+        } else {
+            stack_of(first)
+        };
+    if stack.is_empty() {
+        // Not an error, e.g. the preamble.
+        return;
+    }
+    let mut error = HelgrindError {
+        kind: kind.clone(),
+        what: head.to_string(),
+        opt_thread_id: thread_number(head),
+        stack: stack.clone(),
+        opt_access: if kind == RaceError { parse_access(head) } else { None },
+        opt_conflict: None,
+        opt_lock_order: if kind == LockOrderError { parse_lock_order(head) } else { None },
+        aux: Vec::new(),
+    };
+    let mut locks_held = None;
+    for line in first.iter() {
+        if locks_held.is_none() {
+            locks_held = parse_locks_held(line.as_slice());
+        }
+    }
+    match error.opt_access {
+        Some(ref mut access) => {
+            access.stack = stack;
+            access.locks_held = locks_held.unwrap_or(Vec::new());
+        },
+        None => (),
+    }
+    attach_trailing_lines(&mut error, first.as_slice());
+
+    for paragraph in paragraphs.tail().iter() {
+        let what = match paragraph.as_slice().head() {
+                None => continue,
+                Some(what) => what.clone(),
+            };
+        let stack = stack_of(paragraph);
+        if stack.is_empty() && !what.as_slice().starts_with("Address ") {
+            // Trailing output such as the ERROR SUMMARY.
+            continue;
+        }
+        let conflict = what.as_slice().starts_with("This conflicts with a previous");
+        attach_aux(&mut error, what, stack);
+        if conflict {
+            let locks = paragraph.iter().filter_map(|line| parse_locks_held(line.as_slice())).next();
+            match (&mut error.opt_conflict, locks) {
+                (&Some(ref mut access), Some(locks)) => access.locks_held = locks,
+                _ => (),
+            }
+        }
+        attach_trailing_lines(&mut error, paragraph.as_slice());
+    }
+    report.errors.push(error);
+}
+
+/// Attaches the lines of `paragraph` that follow its stack trace (e.g. "Address 0x601038 is 0
+/// bytes inside data symbol \"var\"") to `error` as auxiliary descriptions.
+fn attach_trailing_lines(error: &mut HelgrindError, paragraph: &[String]) {
+    let mut lines = paragraph.iter()
+        .skip_while(|line| parse_stack_line(line.as_slice()).is_none())
+        .filter(|line| parse_stack_line(line.as_slice()).is_none());
+    loop {
+        match lines.next() {
+            None => break,
+            Some(line) => attach_aux(error, line.clone(), Vec::new()),
+        }
+    }
+}
+
+/// The lines of the current block of output of a process, split into paragraphs.
+struct TextState {
+    index: uint,
+    paragraphs: Vec<Vec<String>>,
+}
+
+/// Flushes the block held by `state` into `report`.
+fn flush_block(report: &mut HelgrindReport, state: &mut TextState) {
+    let paragraphs: Vec<Vec<String>> = mem::replace(&mut state.paragraphs, Vec::new()).move_iter()
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect();
+    parse_text_block(report, paragraphs.as_slice());
+}
+
+/// Parses the plain-text output of Helgrind into a report for each process, in the order in which
+/// the processes first wrote output.
+pub fn parse_log_str(text: &str) -> Vec<HelgrindReport> {
+    let mut reports: Vec<HelgrindReport> = Vec::new();
+    let mut states: HashMap<uint, TextState> = HashMap::new();
+    for line in text.lines() {
+        let (pid, message) = match split_prefix(line) {
+                None => continue,
+                Some(split) => split,
+            };
+        if !states.contains_key(&pid) {
+            reports.push(HelgrindReport::new(Some(pid)));
+            states.insert(pid, TextState {
+                index: reports.len() - 1,
+                paragraphs: vec![Vec::new()],
+            });
+        }
+        let state = states.get_mut(&pid);
+        let trimmed = message.trim();
+        if trimmed.starts_with("---") {
+            flush_block(reports.get_mut(state.index), state);
+            state.paragraphs.push(Vec::new());
+        } else if trimmed.is_empty() {
+            state.paragraphs.push(Vec::new());
+        } else {
+            match state.paragraphs.mut_last() {
+                Some(paragraph) => paragraph.push(trimmed.to_string()),
+                None => state.paragraphs.push(vec![trimmed.to_string()]),
+            }
+        }
+    }
+    for (_, state) in states.mut_iter() {
+        flush_block(reports.get_mut(state.index), state);
+    }
+    reports
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LockOrderError, RaceError, WriteAccess, parse_log_str};
+
+    /// The output of `valgrind --tool=helgrind` for a race on `var`, which one thread writes while
+    /// holding `mu` and the other writes without holding a lock.
+    static RACE_WITH_LOCK: &'static str = "==4242== Helgrind, a thread error detector
+==4242== Copyright (C) 2007-2013, and GNU GPL'd, by OpenWorks LLP et al.
+==4242== Using Valgrind-3.10.1 and LibVEX; rerun with -h for copyright info
+==4242== Command: ./race
+==4242==
+==4242== ---Thread-Announcement------------------------------------------
+==4242==
+==4242== Thread #2 was created
+==4242==    at 0x515543E: clone (clone.S:74)
+==4242==    by 0x4E44199: do_clone.constprop.3 (createthread.c:75)
+==4242==    by 0x4E458BA: pthread_create@@GLIBC_2.2.5 (createthread.c:245)
+==4242==    by 0x4C30C90: pthread_create_WRK (hg_intercepts.c:270)
+==4242==    by 0x4006A5: main (race.c:22)
+==4242==
+==4242== ---Thread-Announcement------------------------------------------
+==4242==
+==4242== Thread #1 is the program's root thread
+==4242==
+==4242== ----------------------------------------------------------------
+==4242==
+==4242==  Lock at 0x601060 was first observed
+==4242==    at 0x4C31DDA: pthread_mutex_init (hg_intercepts.c:518)
+==4242==    by 0x400692: main (race.c:20)
+==4242==  Address 0x601060 is 0 bytes inside data symbol \"mu\"
+==4242==
+==4242== Possible data race during write of size 4 at 0x601040 by thread #2
+==4242== Locks held: 1, at address 0x601060
+==4242==    at 0x400651: child_fn (race.c:12)
+==4242==    by 0x4C30E26: mythread_wrapper (hg_intercepts.c:234)
+==4242==    by 0x4E45181: start_thread (pthread_create.c:312)
+==4242==    by 0x515547C: clone (clone.S:111)
+==4242==
+==4242== This conflicts with a previous write of size 4 by thread #1
+==4242== Locks held: none
+==4242==    at 0x4006B2: main (race.c:24)
+==4242==  Address 0x601040 is 0 bytes inside data symbol \"var\"
+==4242==
+==4242==
+==4242== For counts of detected and suppressed errors, rerun with: -v
+==4242== Use --history-level=approx or =none to gain increased speed, at
+==4242== the cost of reduced accuracy of conflicting-access information
+==4242== ERROR SUMMARY: 1 errors from 1 contexts (suppressed: 0 from 0)
+";
+
+    /// The output of `valgrind --tool=helgrind` for a program that locks `mu1` then `mu2`, and
+    /// later `mu2` then `mu1`.
+    static LOCK_ORDER: &'static str = "==4243== ---Thread-Announcement------------------------------------------
+==4243==
+==4243== Thread #1 is the program's root thread
+==4243==
+==4243== ----------------------------------------------------------------
+==4243==
+==4243== Thread #1: lock order \"0x601040 before 0x601080\" violated
+==4243==
+==4243== Observed (incorrect) order is: acquisition of lock at 0x601080
+==4243==    at 0x4C32145: pthread_mutex_lock (hg_intercepts.c:593)
+==4243==    by 0x4006D6: main (lockorder.c:20)
+==4243==
+==4243==  followed by a later acquisition of lock at 0x601040
+==4243==    at 0x4C32145: pthread_mutex_lock (hg_intercepts.c:593)
+==4243==    by 0x4006E2: main (lockorder.c:21)
+==4243==
+==4243== Required order was established by acquisition of lock at 0x601040
+==4243==    at 0x4C32145: pthread_mutex_lock (hg_intercepts.c:593)
+==4243==    by 0x4006AE: main (lockorder.c:15)
+==4243==
+==4243==  followed by a later acquisition of lock at 0x601080
+==4243==    at 0x4C32145: pthread_mutex_lock (hg_intercepts.c:593)
+==4243==    by 0x4006BA: main (lockorder.c:16)
+==4243==
+==4243==  Lock at 0x601040 was first observed
+==4243==    at 0x4C31DDA: pthread_mutex_init (hg_intercepts.c:518)
+==4243==    by 0x400692: main (lockorder.c:12)
+==4243==  Address 0x601040 is 0 bytes inside data symbol \"mu1\"
+==4243==
+==4243==  Lock at 0x601080 was first observed
+==4243==    at 0x4C31DDA: pthread_mutex_init (hg_intercepts.c:518)
+==4243==    by 0x4006A2: main (lockorder.c:13)
+==4243==  Address 0x601080 is 0 bytes inside data symbol \"mu2\"
+==4243==
+==4243==
+==4243== ERROR SUMMARY: 1 errors from 1 contexts (suppressed: 0 from 0)
+";
+
+    #[test]
+    fn test_lock_order() {
+        let reports = parse_log_str(LOCK_ORDER);
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.errors.len(), 1);
+        let error = &report.errors[0];
+        assert_eq!(error.kind, LockOrderError);
+        assert_eq!(error.opt_thread_id, Some(1));
+        assert_eq!(error.stack.len(), 2);
+        assert_eq!(error.stack[0].fn_name(), "pthread_mutex_lock");
+        assert_eq!(error.stack[1].opt_line, Some(20));
+
+        let lock_order = error.opt_lock_order.as_ref().unwrap();
+        assert_eq!(lock_order.before, 0x601040);
+        assert_eq!(lock_order.after, 0x601080);
+        let observed: Vec<u64> = lock_order.observed.iter().map(|acquisition| acquisition.lock).collect();
+        assert_eq!(observed, vec!(0x601080, 0x601040));
+        let required: Vec<u64> = lock_order.required.iter().map(|acquisition| acquisition.lock).collect();
+        assert_eq!(required, vec!(0x601040, 0x601080));
+        assert_eq!(lock_order.required[0].stack[1].opt_line, Some(15));
+
+        // The lock descriptions that follow are kept as auxiliary descriptions.
+        assert!(error.aux.iter().any(|aux| aux.what.as_slice() == "Lock at 0x601040 was first observed"));
+    }
+
+    #[test]
+    fn test_race_after_lock_announcement() {
+        let reports = parse_log_str(RACE_WITH_LOCK);
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.opt_pid, Some(4242));
+        assert_eq!(report.threads.len(), 2);
+
+        assert_eq!(report.locks.len(), 1);
+        let lock = report.lock(0x601060).unwrap();
+        assert_eq!(lock.opt_lock_id, None);
+        assert_eq!(lock.stack[0].fn_name(), "pthread_mutex_init");
+
+        assert_eq!(report.errors.len(), 1);
+        let error = &report.errors[0];
+        assert_eq!(error.kind, RaceError);
+        assert_eq!(error.opt_thread_id, Some(2));
+        assert_eq!(error.stack[0].fn_name(), "child_fn");
+
+        let access = error.opt_access.as_ref().unwrap();
+        assert_eq!(access.kind, WriteAccess);
+        assert_eq!(access.size, 4);
+        assert_eq!(access.opt_address, Some(0x601040));
+        assert_eq!(access.locks_held, vec!(0x601060));
+
+        let conflict = error.opt_conflict.as_ref().unwrap();
+        assert_eq!(conflict.opt_thread_id, Some(1));
+        assert!(conflict.locks_held.is_empty());
+        assert_eq!(conflict.stack[0].fn_name(), "main");
+    }
+}
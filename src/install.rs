@@ -0,0 +1,141 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Discovery of the installed Valgrind and its default suppressions.
+
+use std::io::{BufferedReader, File};
+use std::option::{Option};
+use std::os;
+use std::result::{Result};
+use std::vec::{Vec};
+
+use super::{IoFailure, ParseError, Suppressions};
+use load::{LoadedSuppressions, load_files};
+use version::{Version, VersionError};
+
+/// Library directories that are searched when neither `VALGRIND_LIB` nor the `valgrind` executable
+/// on the `PATH` lead to an installation.
+static COMMON_LIB_DIRS: &'static [&'static str] = &[
+    "/usr/lib/valgrind",
+    "/usr/lib64/valgrind",
+    "/usr/libexec/valgrind",
+    "/usr/local/lib/valgrind",
+    "/usr/local/libexec/valgrind",
+    "/usr/lib/x86_64-linux-gnu/valgrind",
+    "/opt/local/lib/valgrind",
+];
+
+/// A Valgrind installation.
+#[deriving(Clone, PartialEq)]
+pub struct Installation {
+    /// The path to the `valgrind` executable, if it was found on the `PATH`.
+    pub opt_valgrind_path: Option<Path>,
+    /// The first candidate directory that holds a `default.supp`: the directory named by
+    /// `VALGRIND_LIB`, then the `lib/valgrind`, `lib64/valgrind` and `libexec/valgrind`
+    /// directories of the prefix of `opt_valgrind_path`, then the common library directories.
+    ///
+    /// This is a guess, not necessarily the library directory that `valgrind` uses: e.g. an
+    /// unrelated installation may be found if `valgrind` was moved, or installed without
+    /// `default.supp`. It can therefore belong to a different Valgrind than the one whose
+    /// `--version` `version()` reports.
+    pub lib_dir: Path,
+}
+
+/// Returns whether `dir` looks like a Valgrind library directory.
+fn is_lib_dir(dir: &Path) -> bool {
+    dir.join("default.supp").exists()
+}
+
+/// Returns the path to the `valgrind` executable on the `PATH`, if any.
+fn find_valgrind_on_path() -> Option<Path> {
+    let path_var = match os::getenv("PATH") {
+            None => return None,
+            Some(path_var) => path_var,
+        };
+    os::split_paths(path_var.as_slice()).move_iter()
+        .map(|dir| dir.join("valgrind"))
+        .find(|valgrind_path| valgrind_path.is_file())
+}
+
+impl Installation {
+
+    /// Finds the installed Valgrind. The library directory is the directory named by the
+    /// `VALGRIND_LIB` environment variable if set; otherwise the `lib/valgrind`, `lib64/valgrind`
+    /// or `libexec/valgrind` directory of the prefix of the `valgrind` executable on the `PATH`;
+    /// otherwise the first of the common library directories that contains `default.supp`.
+    pub fn find() -> Option<Installation> {
+        let opt_valgrind_path = find_valgrind_on_path();
+
+        let mut candidates: Vec<Path> = Vec::new();
+        match os::getenv("VALGRIND_LIB") {
+            Some(lib_dir) => candidates.push(Path::new(lib_dir)),
+            None => (),
+        }
+        match opt_valgrind_path {
+            Some(ref valgrind_path) => {
+                // The executable is usually `<prefix>/bin/valgrind`.
+                let prefix = valgrind_path.dir_path().dir_path();
+                candidates.push(prefix.join("lib/valgrind"));
+                candidates.push(prefix.join("lib64/valgrind"));
+                candidates.push(prefix.join("libexec/valgrind"));
+            },
+            None => (),
+        }
+        candidates.extend(COMMON_LIB_DIRS.iter().map(|dir| Path::new(*dir)));
+
+        candidates.move_iter().find(|dir| is_lib_dir(dir)).map(|lib_dir| {
+            Installation {
+                opt_valgrind_path: opt_valgrind_path.clone(),
+                lib_dir: lib_dir,
+            }
+        })
+    }
+
+    /// The path to the default suppressions file, `default.supp`.
+    pub fn default_supp_path(&self) -> Path {
+        self.lib_dir.join("default.supp")
+    }
+
+    /// Parses the default suppressions, which Valgrind applies unless run with
+    /// `--default-suppressions=no`.
+    pub fn default_suppressions(&self) -> Result<Suppressions, ParseError> {
+        let path = self.default_supp_path();
+        let file = match File::open(&path) {
+                Err(e) => {
+                    return Err(ParseError::new(IoFailure, 0, 1, format!("IoError returned: {}", e)).with_path(path));
+                },
+                Ok(file) => file,
+            };
+        Suppressions::parse(&mut BufferedReader::new(file)).map_err(|e| e.with_path(path.clone()))
+    }
+
+    /// Loads the suppressions files at `paths` like `load::load_files`. If `default_suppressions`
+    /// is `true`, the default suppressions are loaded first, as with Valgrind's
+    /// `--default-suppressions=yes`.
+    pub fn load_files(&self, paths: &[Path], default_suppressions: bool) -> Result<LoadedSuppressions, ParseError> {
+        if !default_suppressions {
+            return load_files(paths);
+        }
+        let mut all_paths = vec![self.default_supp_path()];
+        all_paths.push_all(paths);
+        load_files(all_paths.as_slice())
+    }
+
+    /// Queries the version of this installation by running `valgrind --version`. If the
+    /// executable was not found on the `PATH`, `valgrind` is run anyway.
+    pub fn version(&self) -> Result<Version, VersionError> {
+        match self.opt_valgrind_path {
+            Some(ref valgrind_path) => Version::query(valgrind_path),
+            None => Version::query(&Path::new("valgrind")),
+        }
+    }
+}
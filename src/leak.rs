@@ -0,0 +1,212 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Memcheck loss records and the LEAK SUMMARY.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::option::{Option};
+use std::result::{Result};
+use std::vec::{Vec};
+
+use super::{MemcheckLeak, Suppression, Suppressions};
+use log::{parse_count};
+use matching::{compile_frames, matches_stack};
+use report::{DefiniteLeak, IndirectLeak, LeakKind, PossibleLeak, ReachableLeak};
+use report::{LeakSummary, Report, StackFrame, ValgrindError};
+
+/// A loss record of a Memcheck leak check, e.g. "24 (8 direct, 16 indirect) bytes in 1 blocks are
+/// definitely lost in loss record 3 of 3".
+#[deriving(Clone)]
+pub struct LossRecord {
+    /// The kind of leak.
+    pub kind: LeakKind,
+    /// The number of bytes in the blocks of the record.
+    pub direct_bytes: u64,
+    /// The number of bytes in blocks that are only reachable from the blocks of the record.
+    pub indirect_bytes: u64,
+    /// The number of blocks of the record.
+    pub blocks: u64,
+    /// The number of the record, starting at 1.
+    pub record: uint,
+    /// The number of loss records of the leak check.
+    pub total_records: uint,
+    /// The stack trace of the allocation of the blocks, innermost frame first.
+    pub stack: Vec<StackFrame>,
+}
+
+/// Returns the number that follows the word `word` in `words`.
+fn count_after(words: &[&str], word: &str) -> Option<u64> {
+    words.iter().position(|w| *w == word).and_then(|i| {
+        if i + 1 < words.len() { parse_count(words[i + 1]) } else { None }
+    })
+}
+
+impl LossRecord {
+
+    /// Creates a loss record from a Memcheck leak error, which may have been parsed from XML or
+    /// plain-text output. The counts given by XML output are used when present; the rest is
+    /// parsed from the description. Returns `None` if `error` is not a leak error.
+    pub fn from_error(error: &ValgrindError) -> Option<LossRecord> {
+        if error.type_ != MemcheckLeak {
+            return None;
+        }
+        let what = error.what.as_slice();
+        let kind = match error.opt_leak_kind {
+                Some(ref kind) => kind.clone(),
+                None => return None,
+            };
+
+        // "<total> [(<direct> direct, <indirect> indirect)] bytes in <blocks> blocks are ... in
+        // loss record <record> of <total_records>"
+        let words: Vec<&str> = what.words().collect();
+        let words = words.as_slice();
+        let (total_bytes, blocks) = match error.opt_leaked {
+                Some(leaked) => leaked,
+                None => {
+                    match words.head().and_then(|word| parse_count(*word)) {
+                        None => return None,
+                        Some(total_bytes) => (total_bytes, count_after(words, "in").unwrap_or(0)),
+                    }
+                },
+            };
+        // The counts may themselves contain commas, e.g. "(1,024 direct, 16 indirect)".
+        let (direct_bytes, indirect_bytes) = match (what.find('('), what.find(')')) {
+                (Some(open), Some(close)) if open < close => {
+                    let counts = what.slice(open + 1, close);
+                    match (counts.find_str(" direct, "), counts.find_str(" indirect")) {
+                        (Some(direct_end), Some(indirect_end)) if direct_end < indirect_end => {
+                            match (parse_count(counts.slice_to(direct_end)), parse_count(counts.slice(direct_end + 9, indirect_end))) {
+                                (Some(direct_bytes), Some(indirect_bytes)) => (direct_bytes, indirect_bytes),
+                                _ => (total_bytes, 0),
+                            }
+                        },
+                        _ => (total_bytes, 0),
+                    }
+                },
+                _ => (total_bytes, 0),
+            };
+        Some(LossRecord {
+            kind: kind,
+            direct_bytes: direct_bytes,
+            indirect_bytes: indirect_bytes,
+            blocks: blocks,
+            record: count_after(words, "record").unwrap_or(0) as uint,
+            total_records: count_after(words, "of").unwrap_or(0) as uint,
+            stack: error.stack.clone(),
+        })
+    }
+
+    /// The number of bytes lost, including the bytes that are indirectly lost.
+    pub fn total_bytes(&self) -> u64 {
+        self.direct_bytes + self.indirect_bytes
+    }
+
+    /// Returns whether `suppression` suppresses this loss record: it must be a `Memcheck:Leak`
+    /// suppression whose `match-leak-kinds:` (if any) includes the kind of this record, and whose
+    /// calling context matches the allocation stack.
+    pub fn is_suppressed_by(&self, suppression: &Suppression) -> bool {
+        if suppression.type_ != MemcheckLeak {
+            return false;
+        }
+        let kind_matches = match suppression.match_leak_kinds() {
+                None => true,
+                Some(kinds) => kinds.contains(&self.kind),
+            };
+        kind_matches && matches_stack(compile_frames(suppression.frames.as_slice()).as_slice(), self.stack.as_slice())
+    }
+
+    /// Returns the first of `suppressions` that suppresses this loss record.
+    pub fn first_suppression<'a>(&self, suppressions: &'a Suppressions) -> Option<&'a Suppression> {
+        suppressions.suppressions().find(|suppression| self.is_suppressed_by(*suppression))
+    }
+}
+
+impl Show for LossRecord {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        let kind = match self.kind {
+                DefiniteLeak => "definitely lost",
+                IndirectLeak => "indirectly lost",
+                PossibleLeak => "possibly lost",
+                ReachableLeak => "still reachable",
+            };
+        (if self.indirect_bytes == 0 {
+            write!(fmt, "{} bytes", self.direct_bytes)
+        } else {
+            write!(fmt, "{} ({} direct, {} indirect) bytes", self.total_bytes(), self.direct_bytes, self.indirect_bytes)
+        })
+            .and_then(|()| -> Result<(), FormatError> {
+                write!(fmt, " in {} blocks are {} in loss record {:u} of {:u}", self.blocks, kind, self.record, self.total_records)
+            })
+    }
+}
+
+impl LeakSummary {
+
+    /// Computes a leak summary from `records`. The XML output has no LEAK SUMMARY, so this is how
+    /// one is obtained for it. Only the kinds shown with `--show-leak-kinds=` are counted, and
+    /// suppressed leaks are not counted at all.
+    pub fn from_loss_records(records: &[LossRecord]) -> LeakSummary {
+        let mut summary = LeakSummary {
+            definitely_lost_bytes: 0,
+            definitely_lost_blocks: 0,
+            indirectly_lost_bytes: 0,
+            indirectly_lost_blocks: 0,
+            possibly_lost_bytes: 0,
+            possibly_lost_blocks: 0,
+            still_reachable_bytes: 0,
+            still_reachable_blocks: 0,
+            suppressed_bytes: 0,
+            suppressed_blocks: 0,
+        };
+        for record in records.iter() {
+            // Like Valgrind, indirectly lost bytes are only counted in their own category.
+            let (bytes, blocks) = match record.kind {
+                    DefiniteLeak => (&mut summary.definitely_lost_bytes, &mut summary.definitely_lost_blocks),
+                    IndirectLeak => (&mut summary.indirectly_lost_bytes, &mut summary.indirectly_lost_blocks),
+                    PossibleLeak => (&mut summary.possibly_lost_bytes, &mut summary.possibly_lost_blocks),
+                    ReachableLeak => (&mut summary.still_reachable_bytes, &mut summary.still_reachable_blocks),
+                };
+            *bytes += record.direct_bytes;
+            *blocks += record.blocks;
+        }
+        summary
+    }
+
+    /// The number of bytes that are definitely or indirectly lost.
+    pub fn lost_bytes(&self) -> u64 {
+        self.definitely_lost_bytes + self.indirectly_lost_bytes
+    }
+}
+
+impl Report {
+
+    /// Returns the loss records of this report, in the order in which they were reported.
+    pub fn loss_records(&self) -> Vec<LossRecord> {
+        self.errors.iter().filter_map(|error| LossRecord::from_error(error)).collect()
+    }
+
+    /// Returns the LEAK SUMMARY of this report, or one computed from the loss records if the report
+    /// has none (as for XML output). Returns `None` if there is neither.
+    pub fn leak_summary(&self) -> Option<LeakSummary> {
+        match self.opt_leak_summary {
+            Some(ref summary) => Some(summary.clone()),
+            None => {
+                let records = self.loss_records();
+                if records.is_empty() {
+                    None
+                } else {
+                    Some(LeakSummary::from_loss_records(records.as_slice()))
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Loading of several suppressions files into one set, like repeated `--suppressions=` options.
+
+use std::collections::{HashMap};
+use std::fmt::{FormatError, Formatter, Show};
+use std::io::{BufferedReader, File};
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{IoFailure, ParseError, SuppressionReader, Suppressions};
+
+/// The location of a suppression in a suppressions file.
+#[deriving(Clone, PartialEq)]
+pub struct Origin {
+    /// The path to the suppressions file.
+    pub path: Path,
+    /// The line number of the opening brace of the suppression.
+    pub lineno: uint,
+}
+
+impl Show for Origin {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}:{:u}", self.path.display(), self.lineno)
+    }
+}
+
+/// Two suppressions with the same name in different suppressions files.
+#[deriving(Clone, PartialEq, Show)]
+pub struct NameCollision {
+    /// The name of the suppressions.
+    pub name: String,
+    /// The origin of the first suppression with the name.
+    pub first: Origin,
+    /// The origin of a later suppression with the name, in another file.
+    pub second: Origin,
+}
+
+/// The suppressions loaded from several suppressions files.
+#[deriving(Clone)]
+pub struct LoadedSuppressions {
+    /// The suppressions of all the files, in the order in which the files were given.
+    pub suppressions: Suppressions,
+    /// The origin of each suppression, in the same order as `suppressions`.
+    pub origins: Vec<Origin>,
+    /// The suppressions that have the same name as a suppression in an earlier file. Valgrind
+    /// accepts these, but they make the counts of used suppressions ambiguous.
+    pub collisions: Vec<NameCollision>,
+}
+
+/// Loads the suppressions files at `paths` into one set of suppressions, as Valgrind does for
+/// repeated `--suppressions=` options. To also load Valgrind's default suppressions, include the
+/// path to the installation's `default.supp` in `paths`.
+///
+/// Errors, including failures to open a file, carry the path of the file in which they occurred.
+pub fn load_files(paths: &[Path]) -> Result<LoadedSuppressions, ParseError> {
+    let mut suppressions = Vec::new();
+    let mut origins = Vec::new();
+    let mut collisions = Vec::new();
+    let mut first_origins: HashMap<String, Origin> = HashMap::new();
+
+    for path in paths.iter() {
+        let file = match File::open(path) {
+                Err(e) => {
+                    return Err(ParseError::new(IoFailure, 0, 1, format!("IoError returned: {}", e)).with_path(path.clone()));
+                },
+                Ok(file) => file,
+            };
+        let mut buf = BufferedReader::new(file);
+        let mut reader = SuppressionReader::new(&mut buf);
+        loop {
+            let suppression = match reader.next() {
+                    None => break,
+                    Some(Err(e)) => return Err(e.with_path(path.clone())),
+                    Some(Ok(suppression)) => suppression,
+                };
+            let origin = Origin {
+                path: path.clone(),
+                lineno: reader.last_opening_brace_lineno(),
+            };
+            match first_origins.find(&suppression.name) {
+                Some(first) if first.path != *path => {
+                    collisions.push(NameCollision {
+                        name: suppression.name.clone(),
+                        first: first.clone(),
+                        second: origin.clone(),
+                    });
+                },
+                _ => (),
+            }
+            if !first_origins.contains_key(&suppression.name) {
+                first_origins.insert(suppression.name.clone(), origin.clone());
+            }
+            suppressions.push(suppression);
+            origins.push(origin);
+        }
+    }
+
+    Ok(LoadedSuppressions {
+        suppressions: Suppressions {
+            suppressions_: suppressions,
+        },
+        origins: origins,
+        collisions: collisions,
+    })
+}
@@ -0,0 +1,467 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Parsing of the plain-text output of Valgrind, in which every line starts with `==PID==`.
+
+use std::collections::{HashMap};
+use std::from_str::{from_str};
+use std::io::{Buffer, EndOfFile, IoResult};
+use std::mem;
+use std::num::{from_str_radix};
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{SuppressionType};
+use report::{DefiniteLeak, IndirectLeak, LeakKind, PossibleLeak, ReachableLeak};
+use report::{AuxStack, ErrorSummary, HeapSummary, LeakSummary, Report, StackFrame, ValgrindError};
+use xml::{number_after, suppression_tool_name, syscall_param};
+
+/// Splits a line of output into the process ID and the message, or returns `None` if the line
+/// does not start with `==PID==`.
+pub fn split_prefix(line: &str) -> Option<(uint, &str)> {
+    if !line.starts_with("==") {
+        return None;
+    }
+    let rest = line.slice_from(2);
+    let end = match rest.find_str("==") {
+            None => return None,
+            Some(end) => end,
+        };
+    let pid: uint = match from_str(rest.slice_to(end)) {
+            None => return None,
+            Some(pid) => pid,
+        };
+    let message = rest.slice_from(end + 2);
+    Some((pid, if message.starts_with(" ") { message.slice_from(1) } else { message }))
+}
+
+/// Parses a number such as `1,024`, as Valgrind prints counts with grouped digits.
+pub fn parse_count(s: &str) -> Option<u64> {
+    let digits: String = s.trim().chars().filter(|&c| c != ',').collect();
+    from_str(digits.as_slice())
+}
+
+/// Parses "`N` bytes in `M` blocks", returning `(N, M)`.
+fn parse_bytes_in_blocks(s: &str) -> Option<(u64, u64)> {
+    let s = s.trim();
+    let bytes_end = match s.find_str(" bytes in ") {
+            None => return None,
+            Some(bytes_end) => bytes_end,
+        };
+    let rest = s.slice_from(bytes_end + 10);
+    let blocks_end = rest.find(' ').unwrap_or(rest.len());
+    match (parse_count(s.slice_to(bytes_end)), parse_count(rest.slice_to(blocks_end))) {
+        (Some(bytes), Some(blocks)) => Some((bytes, blocks)),
+        _ => None,
+    }
+}
+
+/// Parses a stack trace line, e.g. `at 0x4005A4: main (test.c:7)` or
+/// `by 0x4E5A76D: (below main) (in /lib/libc-2.19.so)`.
+pub fn parse_stack_line(line: &str) -> Option<StackFrame> {
+    let line = line.trim();
+    let rest = if line.starts_with("at ") || line.starts_with("by ") {
+            line.slice_from(3)
+        } else {
+            return None;
+        };
+    let colon = match rest.find_str(": ") {
+            None => return None,
+            Some(colon) => colon,
+        };
+    let ip = rest.slice_to(colon);
+    if !ip.starts_with("0x") {
+        return None;
+    }
+    let mut frame = StackFrame::new();
+    frame.opt_ip = from_str_radix::<u64>(ip.slice_from(2), 16);
+
+    let mut fn_name = rest.slice_from(colon + 2);
+    if fn_name.ends_with(")") {
+        match fn_name.rfind_str(" (") {
+            None => (),
+            Some(open) => {
+                let location = fn_name.slice(open + 2, fn_name.len() - 1);
+                if location.starts_with("in ") {
+                    frame.opt_obj = Some(location.slice_from(3).to_string());
+                    fn_name = fn_name.slice_to(open);
+                } else {
+                    match location.rfind(':').map(|colon| (location.slice_to(colon), from_str(location.slice_from(colon + 1)))) {
+                        Some((path, Some(line))) => {
+                            match path.rfind('/') {
+                                None => frame.opt_file = Some(path.to_string()),
+                                Some(slash) => {
+                                    frame.opt_dir = Some(path.slice_to(slash).to_string());
+                                    frame.opt_file = Some(path.slice_from(slash + 1).to_string());
+                                },
+                            }
+                            frame.opt_line = Some(line);
+                            fn_name = fn_name.slice_to(open);
+                        },
+                        _ => (),
+                    }
+                }
+            },
+        }
+    }
+    if fn_name != "???" && !fn_name.starts_with("0x") {
+        frame.opt_fn_name = Some(fn_name.to_string());
+    }
+    Some(frame)
+}
+
+/// Returns the suppression type, extra information and leak kind of a Memcheck error from its
+/// description. An unrecognized error keeps its description as the suppression type.
+fn classify_memcheck(what: &str) -> (SuppressionType, Option<String>, Option<LeakKind>) {
+    let leak_kinds = [
+        ("are definitely lost", DefiniteLeak),
+        ("are indirectly lost", IndirectLeak),
+        ("are possibly lost", PossibleLeak),
+        ("are still reachable", ReachableLeak),
+    ];
+    for &(phrase, ref kind) in leak_kinds.iter() {
+        if what.contains(phrase) && what.contains("in loss record") {
+            return (SuppressionType::from_parts("Memcheck", "Leak"), None, Some(kind.clone()));
+        }
+    }
+    let kind = if what.starts_with("Invalid read of size ") || what.starts_with("Invalid write of size ") {
+            number_after(what, "of size ").map(|n| format!("Addr{:u}", n))
+        } else if what.starts_with("Use of uninitialised value of size ") {
+            number_after(what, "of size ").map(|n| format!("Value{:u}", n))
+        } else if what.starts_with("Conditional jump or move depends on uninitialised value") {
+            Some(String::from_str("Cond"))
+        } else if what.starts_with("Invalid free()") || what.starts_with("Mismatched free()") {
+            Some(String::from_str("Free"))
+        } else if what.starts_with("Source and destination overlap") {
+            Some(String::from_str("Overlap"))
+        } else if what.starts_with("Syscall param ") {
+            return (SuppressionType::from_parts("Memcheck", "Param"), syscall_param(what), None);
+        } else if what.starts_with("Jump to the invalid address") {
+            Some(String::from_str("Jump"))
+        } else {
+            None
+        };
+    (SuppressionType::from_parts("Memcheck", kind.unwrap_or(what.to_string()).as_slice()), None, None)
+}
+
+/// The section of the output of a process that is being parsed.
+enum Section {
+    /// The preamble, up to the first blank line.
+    Preamble,
+    /// Between errors and summaries.
+    Idle,
+    /// A line that may be the header of an error, if it is followed by a stack trace.
+    PendingHeader(String),
+    /// An error.
+    InError(ValgrindError),
+    /// The HEAP SUMMARY.
+    InHeapSummary,
+    /// The LEAK SUMMARY.
+    InLeakSummary,
+}
+
+/// The parsing state of the output of a single process.
+struct ProcessState {
+    /// The index of the report of the process.
+    index: uint,
+    section: Section,
+}
+
+/// An incremental parser of the plain-text output of Valgrind. The output of several processes
+/// (e.g. with `--trace-children=yes`) may be interleaved; a report is produced for each process ID.
+pub struct LogParser {
+    reports: Vec<Report>,
+    states: HashMap<uint, ProcessState>,
+}
+
+impl LogParser {
+    pub fn new() -> LogParser {
+        LogParser {
+            reports: Vec::new(),
+            states: HashMap::new(),
+        }
+    }
+
+    /// Parses one line of output. Lines that do not start with `==PID==` are ignored.
+    pub fn feed_line(&mut self, line: &str) {
+        let line = line.trim_right_chars(&['\n', '\r']);
+        let (pid, message) = match split_prefix(line) {
+                None => return,
+                Some(split) => split,
+            };
+        if !self.states.contains_key(&pid) {
+            let mut report = Report::new();
+            report.opt_pid = Some(pid);
+            self.reports.push(report);
+            self.states.insert(pid, ProcessState {
+                index: self.reports.len() - 1,
+                section: Preamble,
+            });
+        }
+        let state = self.states.get_mut(&pid);
+        let report = self.reports.get_mut(state.index);
+        let section = mem::replace(&mut state.section, Idle);
+        state.section = next_section(section, message, report);
+    }
+
+    /// Finishes parsing, returning a report for each process in the order in which the processes
+    /// first wrote output.
+    pub fn finish(mut self) -> Vec<Report> {
+        let mut reports = self.reports;
+        for (_, state) in self.states.mut_iter() {
+            let section = mem::replace(&mut state.section, Idle);
+            match section {
+                InError(error) => reports.get_mut(state.index).errors.push(error),
+                _ => (),
+            }
+        }
+        reports
+    }
+}
+
+/// Returns the tool of a report from the first line of the preamble, e.g. `memcheck` from
+/// "Memcheck, a memory error detector".
+fn tool_from_preamble(message: &str) -> Option<String> {
+    message.find(',').and_then(|comma| {
+        let name = message.slice_to(comma);
+        if name.is_empty() || name.contains_char(' ') {
+            None
+        } else {
+            Some(name.chars().map(|c| c.to_lowercase()).collect())
+        }
+    })
+}
+
+/// Applies `message` to `report` and returns the next section.
+fn next_section(section: Section, message: &str, report: &mut Report) -> Section {
+    let trimmed = message.trim();
+    match section {
+        Preamble => {
+            if trimmed.is_empty() {
+                return Idle;
+            }
+            if report.opt_tool.is_none() {
+                report.opt_tool = tool_from_preamble(trimmed);
+            }
+            if trimmed.starts_with("Parent PID: ") {
+                report.opt_ppid = from_str(trimmed.slice_from(12));
+            }
+            Preamble
+        },
+        InError(mut error) => {
+            if trimmed.is_empty() {
+                report.errors.push(error);
+                return Idle;
+            }
+            match parse_stack_line(trimmed) {
+                Some(frame) => {
+                    match error.aux.mut_last() {
+                        Some(aux) => aux.stack.push(frame),
+                        None => error.stack.push(frame),
+                    }
+                },
+                None => {
+                    error.aux.push(AuxStack {
+                        what: trimmed.to_string(),
+                        stack: Vec::new(),
+                    });
+                },
+            }
+            InError(error)
+        },
+        InHeapSummary => {
+            if trimmed.is_empty() {
+                return Idle;
+            }
+            if report.opt_heap_summary.is_none() {
+                report.opt_heap_summary = Some(HeapSummary {
+                    in_use_bytes: 0,
+                    in_use_blocks: 0,
+                    allocs: 0,
+                    frees: 0,
+                    bytes_allocated: 0,
+                });
+            }
+            let summary = report.opt_heap_summary.as_mut().unwrap();
+            if trimmed.starts_with("in use at exit: ") {
+                match parse_bytes_in_blocks(trimmed.slice_from(16)) {
+                    Some((bytes, blocks)) => {
+                        summary.in_use_bytes = bytes;
+                        summary.in_use_blocks = blocks;
+                    },
+                    None => (),
+                }
+            } else if trimmed.starts_with("total heap usage: ") {
+                // "<allocs> allocs, <frees> frees, <bytes> bytes allocated", where the numbers
+                // themselves may contain commas.
+                let usage = trimmed.slice_from(18);
+                let counts = match (usage.find_str(" allocs, "), usage.find_str(" frees, "), usage.find_str(" bytes allocated")) {
+                        (Some(allocs_end), Some(frees_end), Some(bytes_end)) if allocs_end < frees_end && frees_end < bytes_end => {
+                            (parse_count(usage.slice_to(allocs_end)),
+                             parse_count(usage.slice(allocs_end + 9, frees_end)),
+                             parse_count(usage.slice(frees_end + 8, bytes_end)))
+                        },
+                        _ => (None, None, None),
+                    };
+                match counts {
+                    (Some(allocs), Some(frees), Some(bytes_allocated)) => {
+                        summary.allocs = allocs;
+                        summary.frees = frees;
+                        summary.bytes_allocated = bytes_allocated;
+                    },
+                    _ => (),
+                }
+            }
+            InHeapSummary
+        },
+        InLeakSummary => {
+            if trimmed.is_empty() {
+                return Idle;
+            }
+            if report.opt_leak_summary.is_none() {
+                report.opt_leak_summary = Some(LeakSummary {
+                    definitely_lost_bytes: 0,
+                    definitely_lost_blocks: 0,
+                    indirectly_lost_bytes: 0,
+                    indirectly_lost_blocks: 0,
+                    possibly_lost_bytes: 0,
+                    possibly_lost_blocks: 0,
+                    still_reachable_bytes: 0,
+                    still_reachable_blocks: 0,
+                    suppressed_bytes: 0,
+                    suppressed_blocks: 0,
+                });
+            }
+            let summary = report.opt_leak_summary.as_mut().unwrap();
+            let colon = trimmed.find(':').unwrap_or(trimmed.len());
+            match parse_bytes_in_blocks(trimmed.slice_from(colon + 1)) {
+                Some((bytes, blocks)) => {
+                    match trimmed.slice_to(colon) {
+                        "definitely lost" => {
+                            summary.definitely_lost_bytes = bytes;
+                            summary.definitely_lost_blocks = blocks;
+                        },
+                        "indirectly lost" => {
+                            summary.indirectly_lost_bytes = bytes;
+                            summary.indirectly_lost_blocks = blocks;
+                        },
+                        "possibly lost" => {
+                            summary.possibly_lost_bytes = bytes;
+                            summary.possibly_lost_blocks = blocks;
+                        },
+                        "still reachable" => {
+                            summary.still_reachable_bytes = bytes;
+                            summary.still_reachable_blocks = blocks;
+                        },
+                        "suppressed" => {
+                            summary.suppressed_bytes = bytes;
+                            summary.suppressed_blocks = blocks;
+                        },
+                        _ => (),
+                    }
+                },
+                None => (),
+            }
+            InLeakSummary
+        },
+        Idle | PendingHeader(_) => {
+            let opt_header = match section {
+                    PendingHeader(header) => Some(header),
+                    _ => None,
+                };
+            if trimmed.is_empty() {
+                return Idle;
+            }
+            if trimmed == "HEAP SUMMARY:" {
+                return InHeapSummary;
+            } else if trimmed == "LEAK SUMMARY:" {
+                return InLeakSummary;
+            } else if trimmed.starts_with("ERROR SUMMARY: ") {
+                report.opt_error_summary = parse_error_summary(trimmed.slice_from(15));
+                return Idle;
+            }
+            match (opt_header, parse_stack_line(trimmed)) {
+                (Some(header), Some(frame)) => {
+                    let mut error = error_from_header(report, header);
+                    error.stack.push(frame);
+                    InError(error)
+                },
+                (_, Some(_)) => Idle,
+                (_, None) => PendingHeader(trimmed.to_string()),
+            }
+        },
+    }
+}
+
+/// Parses the rest of an ERROR SUMMARY line, e.g. "2 errors from 2 contexts (suppressed: 0 from 0)".
+fn parse_error_summary(s: &str) -> Option<ErrorSummary> {
+    let numbers: Vec<uint> = s.split(|c: char| !c.is_digit() && c != ',')
+        .filter_map(|part| parse_count(part).map(|n| n as uint))
+        .collect();
+    match numbers.as_slice() {
+        [errors, contexts, suppressed, suppressed_contexts, ..] => {
+            Some(ErrorSummary {
+                errors: errors,
+                contexts: contexts,
+                suppressed: suppressed,
+                suppressed_contexts: suppressed_contexts,
+            })
+        },
+        _ => None,
+    }
+}
+
+/// Creates an error of `report` from its header line. Only Memcheck errors are classified; the
+/// errors of other tools keep the header line as their suppression type.
+fn error_from_header(report: &Report, what: String) -> ValgrindError {
+    let tool_name = suppression_tool_name(report.opt_tool.as_ref().map(|tool| tool.as_slice()).unwrap_or(""));
+    let (type_, opt_extra, opt_leak_kind) = if tool_name.as_slice() == "Memcheck" {
+            classify_memcheck(what.as_slice())
+        } else {
+            (SuppressionType::from_parts(tool_name.as_slice(), what.as_slice()), None, None)
+        };
+    ValgrindError {
+        opt_unique: None,
+        type_: type_,
+        opt_extra: opt_extra,
+        opt_leak_kind: opt_leak_kind,
+        opt_leaked: None,
+        what: what,
+        stack: Vec::new(),
+        aux: Vec::new(),
+        opt_suppressed_by: None,
+    }
+}
+
+/// Parses the plain-text output of Valgrind read from `buf` into a report for each process.
+pub fn parse_log<B: Buffer>(buf: &mut B) -> IoResult<Vec<Report>> {
+    let mut parser = LogParser::new();
+    loop {
+        match buf.read_line() {
+            Err(ref e) if e.kind == EndOfFile => break,
+            Err(e) => return Err(e),
+            Ok(line) => parser.feed_line(line.as_slice()),
+        }
+    }
+    Ok(parser.finish())
+}
+
+/// Parses the plain-text output of Valgrind in `text` into a report for each process.
+pub fn parse_log_str(text: &str) -> Vec<Report> {
+    let mut parser = LogParser::new();
+    for line in text.lines() {
+        parser.feed_line(line);
+    }
+    parser.finish()
+}
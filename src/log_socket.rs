@@ -0,0 +1,283 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! A listener for the plain-text output that Valgrind streams with `--log-socket=host:port`, like
+//! `valgrind-listener`.
+
+use std::comm::{Receiver, channel};
+use std::io::{Acceptor, BufferedReader, EndOfFile, IoError, IoResult, Listener};
+use std::io::net::ip::{SocketAddr};
+use std::io::net::tcp::{TcpAcceptor, TcpListener, TcpStream};
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+use std::task;
+use std::vec::{Vec};
+
+use log::{LogParser, split_prefix};
+use report::{Report};
+
+/// The port that Valgrind connects to if `--log-socket=` does not give one.
+pub static DEFAULT_PORT: u16 = 1500;
+
+/// A connection from a process run with `--log-socket=`, which yields the lines of its output as
+/// they arrive.
+pub struct LogConnection {
+    reader: BufferedReader<TcpStream>,
+    opt_pid: Option<uint>,
+    done: bool,
+}
+
+impl LogConnection {
+    pub fn new(stream: TcpStream) -> LogConnection {
+        LogConnection {
+            reader: BufferedReader::new(stream),
+            opt_pid: None,
+            done: false,
+        }
+    }
+
+    /// The process ID of the connected process, once a line with a `==PID==` prefix has been
+    /// received.
+    pub fn pid(&self) -> Option<uint> {
+        self.opt_pid
+    }
+}
+
+impl Iterator<IoResult<String>> for LogConnection {
+    fn next(&mut self) -> Option<IoResult<String>> {
+        if self.done {
+            return None;
+        }
+        match self.reader.read_line() {
+            Err(ref e) if e.kind == EndOfFile => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+            Ok(line) => {
+                if self.opt_pid.is_none() {
+                    self.opt_pid = split_prefix(line.as_slice()).map(|(pid, _)| pid);
+                }
+                Some(Ok(line))
+            },
+        }
+    }
+}
+
+/// The kind of an event of a connection accepted by `LogSocketListener::spawn()`.
+pub enum LogEventKind {
+    /// A line of output, including its line terminator.
+    LineEvent(String),
+    /// The connection was closed, either normally or because of an error. The reports are
+    /// parsed from all of the output of the connection.
+    ClosedEvent(Option<IoError>, Vec<Report>),
+}
+
+/// An event of one of the connections accepted by `LogSocketListener::spawn()`.
+pub struct LogEvent {
+    /// The number of the connection, counting from 0 in the order in which they were accepted.
+    pub connection: uint,
+    /// The process ID of the connected process, if known when the event was received.
+    pub opt_pid: Option<uint>,
+    pub kind: LogEventKind,
+}
+
+/// A TCP listener for processes run with `--log-socket=host:port`. Any number of processes may
+/// be connected at once.
+pub struct LogSocketListener {
+    acceptor: TcpAcceptor,
+}
+
+impl LogSocketListener {
+
+    /// Listens on `addr` and `port`. A port of 0 picks any free port; see `socket_name()`.
+    pub fn bind(addr: &str, port: u16) -> IoResult<LogSocketListener> {
+        let listener = try!(TcpListener::bind(addr, port));
+        Ok(LogSocketListener {
+            acceptor: try!(listener.listen()),
+        })
+    }
+
+    /// The address that the listener is listening on.
+    pub fn socket_name(&mut self) -> IoResult<SocketAddr> {
+        self.acceptor.socket_name()
+    }
+
+    /// Waits for the next process to connect.
+    pub fn accept(&mut self) -> IoResult<LogConnection> {
+        self.acceptor.accept().map(LogConnection::new)
+    }
+
+    /// Accepts connections in a new task, reading each connection in a task of its own, and
+    /// returns a receiver of the events of all the connections. The output of each connection is
+    /// also parsed, and the reports are sent when the connection is closed.
+    pub fn spawn(self) -> Receiver<LogEvent> {
+        let (tx, rx) = channel();
+        let mut acceptor = self.acceptor;
+        task::spawn(proc() {
+            let mut connection = 0u;
+            loop {
+                let stream = match acceptor.accept() {
+                        Err(_) => break,
+                        Ok(stream) => stream,
+                    };
+                let tx = tx.clone();
+                let id = connection;
+                connection = connection + 1;
+                task::spawn(proc() {
+                    let mut log_connection = LogConnection::new(stream);
+                    let mut parser = LogParser::new();
+                    let mut opt_error = None;
+                    loop {
+                        let line = match log_connection.next() {
+                                None => break,
+                                Some(Err(e)) => {
+                                    opt_error = Some(e);
+                                    break;
+                                },
+                                Some(Ok(line)) => line,
+                            };
+                        parser.feed_line(line.as_slice());
+                        let event = LogEvent {
+                            connection: id,
+                            opt_pid: log_connection.pid(),
+                            kind: LineEvent(line),
+                        };
+                        if tx.send_opt(event).is_err() {
+                            return;
+                        }
+                    }
+                    let _ = tx.send_opt(LogEvent {
+                        connection: id,
+                        opt_pid: log_connection.pid(),
+                        kind: ClosedEvent(opt_error, parser.finish()),
+                    });
+                });
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::net::tcp::{TcpStream};
+    use std::task;
+
+    use super::{ClosedEvent, LineEvent, LogSocketListener};
+
+    /// The output of Memcheck for a process that runs a child with `--trace-children=yes`.
+    static PARENT_LOG: &'static str = "==5000== Memcheck, a memory error detector
+==5000== Copyright (C) 2002-2013, and GNU GPL'd, by Julian Seward et al.
+==5000== Using Valgrind-3.10.1 and LibVEX; rerun with -h for copyright info
+==5000== Command: ./parent
+==5000==
+==5000== Invalid read of size 4
+==5000==    at 0x40054A: main (parent.c:7)
+==5000==  Address 0x51f2044 is 0 bytes after a block of size 4 alloc'd
+==5000==    at 0x4C2AB80: malloc (vg_replace_malloc.c:299)
+==5000==    by 0x40053E: main (parent.c:6)
+==5000==
+==5000== ERROR SUMMARY: 1 errors from 1 contexts (suppressed: 0 from 0)
+";
+
+    /// The output of Memcheck for the child of `PARENT_LOG`.
+    static CHILD_LOG: &'static str = "==5001== Memcheck, a memory error detector
+==5001== Copyright (C) 2002-2013, and GNU GPL'd, by Julian Seward et al.
+==5001== Using Valgrind-3.10.1 and LibVEX; rerun with -h for copyright info
+==5001== Command: ./child
+==5001== Parent PID: 5000
+==5001==
+==5001== Invalid write of size 1
+==5001==    at 0x400566: fill (child.c:4)
+==5001==    by 0x400590: main (child.c:11)
+==5001==  Address 0x51f2048 is 0 bytes after a block of size 8 alloc'd
+==5001==    at 0x4C2AB80: malloc (vg_replace_malloc.c:299)
+==5001==    by 0x400584: main (child.c:10)
+==5001==
+==5001== ERROR SUMMARY: 1 errors from 1 contexts (suppressed: 0 from 0)
+";
+
+    /// Sends each log through its own connection to a spawned listener, in chunks of
+    /// `write_size` bytes and concurrently, and checks that the lines and reports of each
+    /// connection are those of its process.
+    fn replay(write_size: uint) {
+        let mut listener = LogSocketListener::bind("127.0.0.1", 0).unwrap();
+        let addr = listener.socket_name().unwrap();
+        let rx = listener.spawn();
+        for log in [PARENT_LOG, CHILD_LOG].iter() {
+            let log = *log;
+            task::spawn(proc() {
+                let mut stream = TcpStream::connect(format!("{}", addr.ip).as_slice(), addr.port).unwrap();
+                for chunk in log.as_bytes().chunks(write_size) {
+                    stream.write(chunk).unwrap();
+                    stream.flush().unwrap();
+                }
+            });
+        }
+
+        let mut texts = vec!(String::new(), String::new());
+        let mut pids = vec!(None, None);
+        let mut closed = 0u;
+        while closed < 2 {
+            let event = rx.recv();
+            assert!(event.connection < 2);
+            assert!(event.opt_pid.is_some());
+            match *pids.get(event.connection) {
+                Some(pid) => assert_eq!(event.opt_pid, Some(pid)),
+                None => *pids.get_mut(event.connection) = event.opt_pid,
+            }
+            match event.kind {
+                LineEvent(line) => texts.get_mut(event.connection).push_str(line.as_slice()),
+                ClosedEvent(opt_error, reports) => {
+                    closed += 1;
+                    assert!(opt_error.is_none());
+                    assert_eq!(reports.len(), 1);
+                    let report = &reports[0];
+                    assert_eq!(report.opt_pid, event.opt_pid);
+                    assert_eq!(report.opt_tool, Some("memcheck".to_string()));
+                    assert_eq!(report.errors.len(), 1);
+                    assert_eq!(report.opt_error_summary.as_ref().unwrap().errors, 1);
+                    if event.opt_pid == Some(5000) {
+                        assert_eq!(report.opt_ppid, None);
+                        assert_eq!(report.errors[0].what, "Invalid read of size 4".to_string());
+                    } else {
+                        assert_eq!(report.opt_ppid, Some(5000));
+                        assert_eq!(report.errors[0].what, "Invalid write of size 1".to_string());
+                        assert_eq!(report.errors[0].stack[0].fn_name(), "fill");
+                    }
+                },
+            }
+        }
+
+        for (text, pid) in texts.iter().zip(pids.iter()) {
+            let expected = match *pid {
+                    Some(5000) => PARENT_LOG,
+                    Some(5001) => CHILD_LOG,
+                    _ => fail!("unexpected process ID {}", pid),
+                };
+            assert_eq!(text.as_slice(), expected);
+        }
+        assert!(pids[0] != pids[1]);
+    }
+
+    #[test]
+    fn test_replay_in_chunks() {
+        for &write_size in [1u, 3, 7, 64, 4096].iter() {
+            replay(write_size);
+        }
+    }
+}
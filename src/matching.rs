@@ -0,0 +1,158 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Matching of suppressions against errors, following the rules of Valgrind's error manager.
+
+use std::iter::{range};
+use std::option::{Option};
+use std::vec::{Vec};
+
+use super::{Frame, FrameWildcard, FunFrame, ObjFrame, SrcFrame, Suppression, Suppressions};
+use super::{MemcheckLeak, MemcheckParam};
+use glob::{Glob};
+use report::{LeakKind, StackFrame, ValgrindError};
+
+impl Suppression {
+
+    /// Returns whether this suppression suppresses `error`.
+    ///
+    /// The suppression type, the extra information and the calling context must all match. The
+    /// calling context matches if its frames match a prefix of the stack trace of `error`, where
+    /// `...` matches zero or more stack frames.
+    ///
+    /// Like Valgrind, `fun:` frames are matched against the function names of the stack trace as
+    /// they are, which must be mangled: the error must come from output generated with
+    /// `--demangle=no` (as `ValgrindCommand` does by default), or C++ and Rust functions will not
+    /// match as they would in Valgrind.
+    pub fn matches(&self, error: &ValgrindError) -> bool {
+        matches_compiled(self, compile_frames(self.frames.as_slice()).as_slice(), error)
+    }
+
+    /// Returns the leak kinds listed in the `match-leak-kinds:` line of a `Memcheck:Leak`
+    /// suppression, or `None` if the suppression matches leaks of any kind.
+    pub fn match_leak_kinds(&self) -> Option<Vec<LeakKind>> {
+        let extra_info = match self.opt_extra_info {
+                None => return None,
+                Some(ref extra_info) => extra_info,
+            };
+        for line in extra_info.iter() {
+            let line = line.as_slice();
+            if line.starts_with("match-leak-kinds:") {
+                let kinds = line.slice_from(17).trim();
+                if kinds == "all" {
+                    return None;
+                } else if kinds == "none" {
+                    return Some(Vec::new());
+                }
+                return Some(kinds.split(',').filter_map(|name| LeakKind::from_name(name.trim())).collect());
+            }
+        }
+        None
+    }
+
+    /// Returns whether the extra information of this suppression matches `error`. Only Memcheck
+    /// `Param` suppressions (the system call parameter) and Memcheck `Leak` suppressions (the
+    /// `match-leak-kinds:` line) have extra information that is matched.
+    pub fn matches_extra_info(&self, error: &ValgrindError) -> bool {
+        match self.type_ {
+            MemcheckParam => {
+                match (&self.opt_extra_info, &error.opt_extra) {
+                    (&Some(ref extra_info), &Some(ref extra)) => {
+                        extra_info.len() > 0 && extra_info[0] == *extra
+                    },
+                    _ => false,
+                }
+            },
+            MemcheckLeak => {
+                match (self.match_leak_kinds(), &error.opt_leak_kind) {
+                    (Some(ref kinds), &Some(ref leak_kind)) => kinds.contains(leak_kind),
+                    _ => true,
+                }
+            },
+            _ => true,
+        }
+    }
+}
+
+impl Suppressions {
+
+    /// Returns the first suppression that suppresses `error`, trying the suppressions in order.
+    pub fn first_match<'a>(&'a self, error: &ValgrindError) -> Option<&'a Suppression> {
+        self.suppressions().find(|suppression| suppression.matches(error))
+    }
+}
+
+/// A frame of a calling context with its glob compiled, so that matching a calling context
+/// against a stack compiles each glob once rather than once per comparison.
+pub enum CompiledFrame {
+    /// `...`
+    CompiledWildcard,
+    CompiledObj(Glob),
+    CompiledFun(Glob),
+    /// The glob of the source file, and the line number if any.
+    CompiledSrc(Glob, Option<uint>),
+}
+
+/// Compiles the globs of the calling context `frames`.
+pub fn compile_frames(frames: &[Frame]) -> Vec<CompiledFrame> {
+    frames.iter().map(|frame| {
+        match *frame {
+            FrameWildcard => CompiledWildcard,
+            ObjFrame { glob: ref glob } => CompiledObj(Glob::new(glob.as_slice())),
+            FunFrame { glob: ref glob } => CompiledFun(Glob::new(glob.as_slice())),
+            SrcFrame { glob: ref glob, opt_line: opt_line } => CompiledSrc(Glob::new(glob.as_slice()), opt_line),
+        }
+    }).collect()
+}
+
+/// Returns whether `suppression` suppresses `error`, like `Suppression::matches()`, given the
+/// calling context of `suppression` compiled by `compile_frames()`. This lets the calling context
+/// be compiled once when it is matched against many errors.
+pub fn matches_compiled(suppression: &Suppression, frames: &[CompiledFrame], error: &ValgrindError) -> bool {
+    suppression.type_ == error.type_
+        && suppression.matches_extra_info(error)
+        && matches_stack(frames, error.stack.as_slice())
+}
+
+/// Returns whether the calling context `frames` matches a prefix of `stack`.
+pub fn matches_stack(frames: &[CompiledFrame], stack: &[StackFrame]) -> bool {
+    match frames.head() {
+        None => true,
+        Some(&CompiledWildcard) => {
+            range(0, stack.len() + 1).any(|skip| matches_stack(frames.tail(), stack.slice_from(skip)))
+        },
+        Some(frame) => {
+            match stack.head() {
+                None => false,
+                Some(stack_frame) => {
+                    matches_frame(frame, stack_frame) && matches_stack(frames.tail(), stack.tail())
+                },
+            }
+        },
+    }
+}
+
+/// Returns whether a single frame of a calling context matches `stack_frame`.
+pub fn matches_frame(frame: &CompiledFrame, stack_frame: &StackFrame) -> bool {
+    match *frame {
+        CompiledWildcard => true,
+        CompiledObj(ref glob) => glob.matches(stack_frame.obj_name()),
+        CompiledFun(ref glob) => glob.matches(stack_frame.fn_name()),
+        CompiledSrc(ref glob, opt_line) => {
+            let file = match stack_frame.opt_file {
+                    None => "???",
+                    Some(ref file) => file.as_slice(),
+                };
+            glob.matches(file) && (opt_line.is_none() || opt_line == stack_frame.opt_line)
+        },
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Memcheck client requests, which tell Memcheck about the state of memory. All of them do nothing
+//! when not run on Valgrind.
+//!
+//! # See also
+//! * [Client Requests](http://valgrind.org/docs/manual/mc-manual.html#mc-manual.clientreqs). Valgrind User Manual.
+
+use std::c_str::{ToCStr};
+use std::cmp;
+use std::mem;
+use std::option::{Option};
+use std::result::{Result};
+use std::task;
+
+use client_request::{client_request, running_on_valgrind};
+
+static MAKE_MEM_NOACCESS: uint = 0x4d430000;
+static MAKE_MEM_UNDEFINED: uint = 0x4d430001;
+static MAKE_MEM_DEFINED: uint = 0x4d430002;
+static DISCARD: uint = 0x4d430003;
+static CHECK_MEM_IS_ADDRESSABLE: uint = 0x4d430004;
+static CHECK_MEM_IS_DEFINED: uint = 0x4d430005;
+static DO_LEAK_CHECK: uint = 0x4d430006;
+static COUNT_LEAKS: uint = 0x4d430007;
+static CREATE_BLOCK: uint = 0x4d43000a;
+static MAKE_MEM_DEFINED_IF_ADDRESSABLE: uint = 0x4d43000b;
+static COUNT_LEAK_BLOCKS: uint = 0x4d43000c;
+
+/// Returns the address and the size in bytes of `data`.
+fn address_and_len<T>(data: &[T]) -> (uint, uint) {
+    (data.as_ptr() as uint, data.len() * mem::size_of::<T>())
+}
+
+/// Marks the `len` bytes at `addr` as unaddressable.
+pub unsafe fn make_mem_noaccess_ptr(addr: *const u8, len: uint) {
+    client_request(MAKE_MEM_NOACCESS, &[addr as uint, len]);
+}
+
+/// Marks the `len` bytes at `addr` as addressable, but undefined.
+pub unsafe fn make_mem_undefined_ptr(addr: *const u8, len: uint) {
+    client_request(MAKE_MEM_UNDEFINED, &[addr as uint, len]);
+}
+
+/// Marks the `len` bytes at `addr` as addressable and defined.
+pub unsafe fn make_mem_defined_ptr(addr: *const u8, len: uint) {
+    client_request(MAKE_MEM_DEFINED, &[addr as uint, len]);
+}
+
+/// Marks the addressable bytes of the `len` bytes at `addr` as defined, leaving unaddressable
+/// bytes unaddressable.
+pub unsafe fn make_mem_defined_if_addressable_ptr(addr: *const u8, len: uint) {
+    client_request(MAKE_MEM_DEFINED_IF_ADDRESSABLE, &[addr as uint, len]);
+}
+
+/// Marks the memory of `data` as unaddressable, so that Memcheck reports any access to it.
+pub fn make_mem_noaccess<T>(data: &[T]) {
+    let (addr, len) = address_and_len(data);
+    unsafe { client_request(MAKE_MEM_NOACCESS, &[addr, len]); }
+}
+
+/// Marks the memory of `data` as addressable, but undefined.
+pub fn make_mem_undefined<T>(data: &[T]) {
+    let (addr, len) = address_and_len(data);
+    unsafe { client_request(MAKE_MEM_UNDEFINED, &[addr, len]); }
+}
+
+/// Marks the memory of `data` as addressable and defined.
+pub fn make_mem_defined<T>(data: &[T]) {
+    let (addr, len) = address_and_len(data);
+    unsafe { client_request(MAKE_MEM_DEFINED, &[addr, len]); }
+}
+
+/// Marks the addressable bytes of `data` as defined.
+pub fn make_mem_defined_if_addressable<T>(data: &[T]) {
+    let (addr, len) = address_and_len(data);
+    unsafe { client_request(MAKE_MEM_DEFINED_IF_ADDRESSABLE, &[addr, len]); }
+}
+
+/// Converts the result of a `CHECK_MEM_IS_*` request, which is 0 or the address of the first
+/// offending byte, to a result with the offset of that byte from `addr`.
+fn check_result(result: uint, addr: uint) -> Result<(), uint> {
+    if result == 0 { Ok(()) } else { Err(result - addr) }
+}
+
+/// Checks that the `len` bytes at `addr` are addressable. If not, Memcheck reports an error and
+/// the offset of the first unaddressable byte is returned.
+pub unsafe fn check_mem_is_addressable_ptr(addr: *const u8, len: uint) -> Result<(), uint> {
+    check_result(client_request(CHECK_MEM_IS_ADDRESSABLE, &[addr as uint, len]), addr as uint)
+}
+
+/// Checks that the `len` bytes at `addr` are addressable and defined. If not, Memcheck reports an
+/// error and the offset of the first offending byte is returned.
+pub unsafe fn check_mem_is_defined_ptr(addr: *const u8, len: uint) -> Result<(), uint> {
+    check_result(client_request(CHECK_MEM_IS_DEFINED, &[addr as uint, len]), addr as uint)
+}
+
+/// Checks that the memory of `data` is addressable. If not, Memcheck reports an error and the
+/// byte offset of the first unaddressable byte is returned.
+pub fn check_mem_is_addressable<T>(data: &[T]) -> Result<(), uint> {
+    let (addr, len) = address_and_len(data);
+    unsafe { check_result(client_request(CHECK_MEM_IS_ADDRESSABLE, &[addr, len]), addr) }
+}
+
+/// Checks that the memory of `data` is addressable and defined. If not, Memcheck reports an
+/// error and the byte offset of the first offending byte is returned.
+pub fn check_mem_is_defined<T>(data: &[T]) -> Result<(), uint> {
+    let (addr, len) = address_and_len(data);
+    unsafe { check_result(client_request(CHECK_MEM_IS_DEFINED, &[addr, len]), addr) }
+}
+
+/// A block description created by `create_block()`, which Memcheck uses to describe addresses
+/// within the block in its errors.
+pub struct BlockHandle(uint);
+
+/// Attaches the description `desc` to the memory of `data`, until the block is discarded with
+/// `discard()`.
+pub fn create_block<T>(data: &[T], desc: &str) -> BlockHandle {
+    let (addr, len) = address_and_len(data);
+    // Memcheck copies the description.
+    let handle = desc.with_c_str(|desc| unsafe {
+        client_request(CREATE_BLOCK, &[addr, len, desc as uint])
+    });
+    BlockHandle(handle)
+}
+
+/// Discards a block description created by `create_block()`.
+pub fn discard(handle: BlockHandle) {
+    let BlockHandle(handle) = handle;
+    unsafe { client_request(DISCARD, &[0, handle]); }
+}
+
+/// The counts of a leak check, in bytes or in blocks.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct LeakCounts {
+    /// Definitely and indirectly lost.
+    pub leaked: uint,
+    /// Possibly lost.
+    pub dubious: uint,
+    /// Still reachable.
+    pub reachable: uint,
+    /// Suppressed.
+    pub suppressed: uint,
+}
+
+/// Issues a `DO_LEAK_CHECK` request with the arguments `args`.
+fn leak_check_request(args: [uint, ..2]) {
+    unsafe { client_request(DO_LEAK_CHECK, args.as_slice()); }
+}
+
+/// Runs a full leak check now, like the one at exit.
+pub fn do_leak_check() {
+    leak_check_request([0, 0]);
+}
+
+/// Runs a leak check that only reports the loss records that grew since the previous leak check.
+pub fn do_added_leak_check() {
+    leak_check_request([0, 1]);
+}
+
+/// Runs a leak check that only reports the loss records that changed since the previous leak check.
+pub fn do_changed_leak_check() {
+    leak_check_request([0, 2]);
+}
+
+/// Runs a leak check that only reports a summary.
+pub fn do_quick_leak_check() {
+    leak_check_request([1, 0]);
+}
+
+/// Issues `request` (`COUNT_LEAKS` or `COUNT_LEAK_BLOCKS`) and returns the counts.
+fn count_request(request: uint) -> LeakCounts {
+    let mut counts = [0u, 0, 0, 0];
+    let addr = counts.as_mut_ptr() as uint;
+    let size = mem::size_of::<uint>();
+    unsafe {
+        client_request(request, &[addr, addr + size, addr + 2 * size, addr + 3 * size]);
+    }
+    LeakCounts {
+        leaked: counts[0],
+        dubious: counts[1],
+        reachable: counts[2],
+        suppressed: counts[3],
+    }
+}
+
+/// Returns the numbers of bytes found by the most recent leak check. These are all 0 natively.
+pub fn count_leaks() -> LeakCounts {
+    count_request(COUNT_LEAKS)
+}
+
+/// Returns the numbers of blocks found by the most recent leak check. These are all 0 natively.
+pub fn count_leak_blocks() -> LeakCounts {
+    count_request(COUNT_LEAK_BLOCKS)
+}
+
+/// A guard that fails the task when dropped if definitely or indirectly lost memory was added
+/// since it was created. It does nothing when not run on Valgrind.
+pub struct NoLeakGuard {
+    opt_baseline: Option<(LeakCounts, LeakCounts)>,
+}
+
+impl NoLeakGuard {
+    /// Runs a leak check to get the baseline counts.
+    pub fn new() -> NoLeakGuard {
+        if running_on_valgrind() == 0 {
+            return NoLeakGuard {
+                opt_baseline: None,
+            };
+        }
+        do_quick_leak_check();
+        NoLeakGuard {
+            opt_baseline: Some((count_leaks(), count_leak_blocks())),
+        }
+    }
+}
+
+impl Drop for NoLeakGuard {
+    fn drop(&mut self) {
+        let (bytes, blocks) = match self.opt_baseline {
+                None => return,
+                Some((ref bytes, ref blocks)) => (bytes.clone(), blocks.clone()),
+            };
+        do_added_leak_check();
+        let (new_bytes, new_blocks) = (count_leaks(), count_leak_blocks());
+        // Failing while already failing would abort.
+        if new_bytes.leaked > bytes.leaked && !task::failing() {
+            fail!("{:u} bytes in {:u} blocks were leaked", new_bytes.leaked - bytes.leaked,
+                  new_blocks.leaked - cmp::min(blocks.leaked, new_blocks.leaked));
+        }
+    }
+}
+
+/// Runs `f`, failing if it leaked definitely or indirectly lost memory. This does nothing more
+/// than running `f` when not run on Valgrind.
+pub fn assert_no_leaks<T>(f: || -> T) -> T {
+    let _guard = NoLeakGuard::new();
+    f()
+}
+
+/// Evaluates a block, failing if it leaked definitely or indirectly lost memory.
+#[macro_export]
+macro_rules! assert_no_leaks(
+    ($body:block) => ({
+        let _guard = ::valgrind::memcheck::NoLeakGuard::new();
+        $body
+    });
+)
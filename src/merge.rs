@@ -0,0 +1,375 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Merging of the logs of the processes of a `--trace-children=yes` run, written to files named
+//! by a `--log-file=` template such as `vg.%p.log`.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::io::{BufferedReader, File, InvalidInput, IoError, IoResult};
+use std::io::fs;
+use std::option::{Option};
+use std::os;
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use glob::{Glob};
+use log::{parse_log};
+use report::{ErrorSummary, Report, ValgrindError};
+
+/// A part of a log file name template.
+#[deriving(Clone, PartialEq, Show)]
+enum TemplatePart {
+    Literal(String),
+    /// `%p`, the process ID.
+    Pid,
+    /// `%q{VAR}`, the value of the environment variable `VAR`.
+    EnvVar(String),
+    /// `%n`, a sequence number of the files of a process.
+    Sequence,
+}
+
+/// A log file name template, as given to `--log-file=` or `--xml-file=`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct LogFileTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+/// An error in a log file name template, for which Valgrind would refuse to start.
+#[deriving(Clone, PartialEq)]
+pub struct TemplateError {
+    /// Description of the error.
+    pub message: String,
+}
+
+impl Show for TemplateError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}", self.message.as_slice())
+    }
+}
+
+/// Escapes the glob characters of `s`.
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '*' || c == '?' || c == '\\' {
+            escaped.push_char('\\');
+        }
+        escaped.push_char(c);
+    }
+    escaped
+}
+
+impl LogFileTemplate {
+
+    /// Parses a template. Like Valgrind, `%%` stands for `%`, and a `%` that does not start one
+    /// of the recognized sequences (`%p`, `%n`, `%q{VAR}` and `%%`) is an error.
+    pub fn new(template: &str) -> Result<LogFileTemplate, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        loop {
+            let percent = match rest.find('%') {
+                    None => break,
+                    Some(percent) => percent,
+                };
+            literal.push_str(rest.slice_to(percent));
+            let after = rest.slice_from(percent + 1);
+            let (opt_part, consumed) = if after.starts_with("%") {
+                    literal.push_char('%');
+                    (None, 1)
+                } else if after.starts_with("p") {
+                    (Some(Pid), 1)
+                } else if after.starts_with("n") {
+                    (Some(Sequence), 1)
+                } else if after.starts_with("q{") && after.contains_char('}') {
+                    let close = after.find('}').unwrap();
+                    (Some(EnvVar(after.slice(2, close).to_string())), close + 1)
+                } else if after.starts_with("q") {
+                    return Err(TemplateError {
+                        message: format!("expected '{{' after '%q' in '{}'", template),
+                    });
+                } else {
+                    return Err(TemplateError {
+                        message: format!("expected 'p', 'n', 'q' or '%' after '%' in '{}'", template),
+                    });
+                };
+            match opt_part {
+                Some(part) => {
+                    if !literal.is_empty() {
+                        parts.push(Literal(literal.clone()));
+                        literal.truncate(0);
+                    }
+                    parts.push(part);
+                },
+                None => (),
+            }
+            rest = after.slice_from(consumed);
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Literal(literal));
+        }
+        Ok(LogFileTemplate {
+            parts: parts,
+        })
+    }
+
+    /// Expands the template for the process `pid` and the sequence number `n`. Environment
+    /// variables are looked up with `getenv`. Valgrind refuses to start if a variable is unset;
+    /// here, an unset variable expands to the empty string.
+    pub fn expand(&self, pid: uint, n: uint, getenv: |&str| -> Option<String>) -> String {
+        let mut expanded = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Literal(ref literal) => expanded.push_str(literal.as_slice()),
+                Pid => expanded.push_str(pid.to_string().as_slice()),
+                EnvVar(ref var) => expanded.push_str(getenv(var.as_slice()).unwrap_or(String::new()).as_slice()),
+                Sequence => expanded.push_str(n.to_string().as_slice()),
+            }
+        }
+        expanded
+    }
+
+    /// Returns a glob that matches the names that the template expands to for any process ID and
+    /// sequence number. Environment variables are expanded with the current environment.
+    pub fn glob(&self) -> Glob {
+        let mut pattern = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Literal(ref literal) => pattern.push_str(escape_glob(literal.as_slice()).as_slice()),
+                Pid | Sequence => pattern.push_char('*'),
+                EnvVar(ref var) => {
+                    let value = os::getenv(var.as_slice()).unwrap_or(String::new());
+                    pattern.push_str(escape_glob(value.as_slice()).as_slice());
+                },
+            }
+        }
+        Glob::new(pattern.as_slice())
+    }
+
+    /// Returns the paths of the existing files that the template may have expanded to, sorted.
+    /// `%p` and `%n` may also be in the names of directories, which are then searched for the
+    /// directories that match. Environment variables are expanded with the current environment.
+    pub fn collect(&self) -> IoResult<Vec<Path>> {
+        // The glob escapes characters such as `*` that may be in the names, but never `/`, so it
+        // can be matched one path component at a time.
+        let glob = self.glob();
+        let pattern = glob.as_str();
+        let components: Vec<Glob> = pattern.split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| Glob::new(component))
+            .collect();
+        let (file_glob, dir_globs) = match components.as_slice().last() {
+                None => return Ok(Vec::new()),
+                Some(file_glob) => (file_glob, components.as_slice().init()),
+            };
+
+        let mut dirs = vec!(Path::new(if pattern.starts_with("/") { "/" } else { "." }));
+        for dir_glob in dir_globs.iter() {
+            let mut matched = Vec::new();
+            for dir in dirs.iter() {
+                if dir_glob.is_literal() {
+                    matched.push(dir.join(dir_glob.literal_prefix()));
+                } else {
+                    matched.extend(try!(fs::readdir(dir)).move_iter().filter(|path| {
+                        path.is_dir() && path.filename_str().map(|name| dir_glob.matches(name)).unwrap_or(false)
+                    }));
+                }
+            }
+            dirs = matched;
+        }
+
+        let mut paths: Vec<Path> = Vec::new();
+        for dir in dirs.iter() {
+            paths.extend(try!(fs::readdir(dir)).move_iter().filter(|path| {
+                path.is_file() && path.filename_str().map(|name| file_glob.matches(name)).unwrap_or(false)
+            }));
+        }
+        paths.sort_by(|a, b| a.as_vec().cmp(&b.as_vec()));
+        Ok(paths)
+    }
+}
+
+/// A process of a process tree.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ProcessNode {
+    /// The process ID of the process.
+    pub pid: uint,
+    /// The process ID of the parent process, if given in the preamble.
+    pub opt_ppid: Option<uint>,
+    /// The process IDs of the child processes, in the order in which their logs were read.
+    pub children: Vec<uint>,
+}
+
+/// The tree of the processes of a run, built from the `Parent PID:` lines of the preambles.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ProcessTree {
+    /// The processes, in depth-first order.
+    pub nodes: Vec<ProcessNode>,
+}
+
+impl ProcessTree {
+
+    /// Builds the tree of the processes of `reports`. A process whose parent is not among the
+    /// reports is a root.
+    pub fn new(reports: &[Report]) -> ProcessTree {
+        let pids: Vec<uint> = reports.iter().filter_map(|report| report.opt_pid).collect();
+        let mut unordered: Vec<ProcessNode> = reports.iter().filter_map(|report| {
+                report.opt_pid.map(|pid| {
+                    ProcessNode {
+                        pid: pid,
+                        opt_ppid: report.opt_ppid,
+                        children: Vec::new(),
+                    }
+                })
+            }).collect();
+        for report in reports.iter() {
+            match (report.opt_pid, report.opt_ppid) {
+                (Some(pid), Some(ppid)) => {
+                    match unordered.mut_iter().find(|node| node.pid == ppid) {
+                        Some(parent) => parent.children.push(pid),
+                        None => (),
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        let mut stack: Vec<uint> = unordered.iter()
+            .filter(|node| node.opt_ppid.map(|ppid| !pids.contains(&ppid)).unwrap_or(true))
+            .map(|node| node.pid)
+            .collect();
+        stack.reverse();
+        loop {
+            let pid = match stack.pop() {
+                    None => break,
+                    Some(pid) => pid,
+                };
+            if nodes.iter().any(|node: &ProcessNode| node.pid == pid) {
+                // The PID was reused; the tree is already complete below it.
+                continue;
+            }
+            let node = unordered.iter().find(|node| node.pid == pid).unwrap().clone();
+            stack.extend(node.children.iter().rev().map(|pid| *pid));
+            nodes.push(node);
+        }
+        ProcessTree {
+            nodes: nodes,
+        }
+    }
+
+    /// The processes whose parent is not in the tree.
+    pub fn roots<'a>(&'a self) -> Vec<&'a ProcessNode> {
+        self.nodes.iter().filter(|node| {
+            node.opt_ppid.map(|ppid| self.node(ppid).is_none()).unwrap_or(true)
+        }).collect()
+    }
+
+    /// Returns the process `pid`.
+    pub fn node<'a>(&'a self, pid: uint) -> Option<&'a ProcessNode> {
+        self.nodes.iter().find(|node| node.pid == pid)
+    }
+
+    /// Returns the depth of the process `pid`, where roots have depth 0.
+    pub fn depth(&self, pid: uint) -> uint {
+        let mut depth = 0;
+        let mut opt_node = self.node(pid);
+        loop {
+            match opt_node.and_then(|node| node.opt_ppid).and_then(|ppid| self.node(ppid)) {
+                None => return depth,
+                Some(parent) => {
+                    depth = depth + 1;
+                    opt_node = Some(parent);
+                },
+            }
+            if depth > self.nodes.len() {
+                return depth;
+            }
+        }
+    }
+}
+
+/// The reports of all the processes of a run, merged.
+#[deriving(Clone)]
+pub struct MergedReport {
+    /// The reports, in the depth-first order of the process tree, followed by any reports without
+    /// a process ID.
+    pub reports: Vec<Report>,
+    /// The tree of the processes that have a process ID.
+    pub tree: ProcessTree,
+    /// The errors of all processes, with the process ID of each, in the order of `reports`.
+    pub errors: Vec<(Option<uint>, ValgrindError)>,
+    /// The sum of the ERROR SUMMARY of each process that reported one.
+    pub opt_error_summary: Option<ErrorSummary>,
+}
+
+/// Merges the reports of the processes of a run.
+pub fn merge_reports(reports: Vec<Report>) -> MergedReport {
+    let tree = ProcessTree::new(reports.as_slice());
+    let mut remaining: Vec<Option<Report>> = reports.move_iter().map(|report| Some(report)).collect();
+    let mut ordered = Vec::new();
+    for node in tree.nodes.iter() {
+        match remaining.iter().position(|opt_report| opt_report.as_ref().and_then(|report| report.opt_pid) == Some(node.pid)) {
+            Some(i) => ordered.push(remaining.get_mut(i).take().unwrap()),
+            None => (),
+        }
+    }
+    ordered.extend(remaining.move_iter().filter_map(|opt_report| opt_report));
+
+    let mut errors = Vec::new();
+    let mut opt_error_summary: Option<ErrorSummary> = None;
+    for report in ordered.iter() {
+        errors.extend(report.errors.iter().map(|error| (report.opt_pid, error.clone())));
+        match report.opt_error_summary {
+            None => (),
+            Some(ref summary) => {
+                opt_error_summary = Some(match opt_error_summary {
+                    None => summary.clone(),
+                    Some(total) => ErrorSummary {
+                        errors: total.errors + summary.errors,
+                        contexts: total.contexts + summary.contexts,
+                        suppressed: total.suppressed + summary.suppressed,
+                        suppressed_contexts: total.suppressed_contexts + summary.suppressed_contexts,
+                    },
+                });
+            },
+        }
+    }
+
+    MergedReport {
+        reports: ordered,
+        tree: tree,
+        errors: errors,
+        opt_error_summary: opt_error_summary,
+    }
+}
+
+/// Collects the log files that `template` may have expanded to, parses them and merges the reports
+/// of all the processes. An invalid template is an `InvalidInput` error.
+pub fn merge_logs(template: &str) -> IoResult<MergedReport> {
+    let template = try!(LogFileTemplate::new(template).map_err(|e| {
+            IoError {
+                kind: InvalidInput,
+                desc: "invalid log file template",
+                detail: Some(e.message),
+            }
+        }));
+    let paths = try!(template.collect());
+    let mut reports = Vec::new();
+    for path in paths.iter() {
+        let file = try!(File::open(path));
+        reports.push_all_move(try!(parse_log(&mut BufferedReader::new(file))));
+    }
+    Ok(merge_reports(reports))
+}
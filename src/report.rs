@@ -0,0 +1,266 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Data structures for the errors found in a Valgrind report.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{SuppressionType};
+
+/// The kind of a Memcheck leak, as named in the `match-leak-kinds:` line of a `Memcheck:Leak`
+/// suppression.
+#[deriving(Clone, PartialEq, Eq, Hash)]
+pub enum LeakKind {
+    /// A block to which no pointer was found.
+    DefiniteLeak,
+    /// A block that is only pointed to by definitely lost blocks.
+    IndirectLeak,
+    /// A block to which only interior pointers were found.
+    PossibleLeak,
+    /// A block to which a start-pointer was found.
+    ReachableLeak,
+}
+
+impl LeakKind {
+    /// Parses the name of a leak kind as written in a `match-leak-kinds:` line (e.g. `definite`).
+    pub fn from_name(name: &str) -> Option<LeakKind> {
+        match name {
+            "definite" => Some(DefiniteLeak),
+            "indirect" => Some(IndirectLeak),
+            "possible" => Some(PossibleLeak),
+            "reachable" => Some(ReachableLeak),
+            _ => None,
+        }
+    }
+
+    /// The name of the leak kind as written in a `match-leak-kinds:` line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DefiniteLeak => "definite",
+            IndirectLeak => "indirect",
+            PossibleLeak => "possible",
+            ReachableLeak => "reachable",
+        }
+    }
+}
+
+impl Show for LeakKind {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+/// A single frame of the stack trace of an error.
+#[deriving(Clone)]
+pub struct StackFrame {
+    /// The instruction pointer, if known.
+    pub opt_ip: Option<u64>,
+    /// The path to the object file containing the instruction, if known.
+    pub opt_obj: Option<String>,
+    /// The name of the function containing the instruction, if known.
+    pub opt_fn_name: Option<String>,
+    /// The directory of the source file, if known.
+    pub opt_dir: Option<String>,
+    /// The name of the source file, if known.
+    pub opt_file: Option<String>,
+    /// The line number in the source file, if known.
+    pub opt_line: Option<uint>,
+}
+
+impl StackFrame {
+    /// Creates a stack frame about which nothing is known.
+    pub fn new() -> StackFrame {
+        StackFrame {
+            opt_ip: None,
+            opt_obj: None,
+            opt_fn_name: None,
+            opt_dir: None,
+            opt_file: None,
+            opt_line: None,
+        }
+    }
+
+    /// The object name that an `obj:` frame of a suppression is matched against. Like Valgrind,
+    /// this is `"???"` if the object is unknown.
+    pub fn obj_name<'a>(&'a self) -> &'a str {
+        match self.opt_obj {
+            None => "???",
+            Some(ref obj) => obj.as_slice(),
+        }
+    }
+
+    /// The function name that a `fun:` frame of a suppression is matched against. Like Valgrind,
+    /// this is `"???"` if the function is unknown.
+    pub fn fn_name<'a>(&'a self) -> &'a str {
+        match self.opt_fn_name {
+            None => "???",
+            Some(ref fn_name) => fn_name.as_slice(),
+        }
+    }
+}
+
+impl Show for StackFrame {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        (match self.opt_ip {
+            None => write!(fmt, "???: "),
+            Some(ip) => write!(fmt, "0x{:X}: ", ip),
+        })
+            .and_then(|()| -> Result<(), FormatError> {
+                write!(fmt, "{}", self.fn_name())
+            })
+            .and_then(|()| -> Result<(), FormatError> {
+                match (&self.opt_file, self.opt_line) {
+                    (&Some(ref file), Some(line)) => write!(fmt, " ({}:{:u})", file.as_slice(), line),
+                    _ => write!(fmt, " (in {})", self.obj_name()),
+                }
+            })
+    }
+}
+
+/// A single error reported by a Valgrind tool.
+#[deriving(Clone)]
+pub struct ValgrindError {
+    /// The identifier of the error within its report, if known.
+    pub opt_unique: Option<u64>,
+    /// The type of suppression that is able to suppress this error.
+    pub type_: SuppressionType,
+    /// The tool-specific string that is compared against the extra information of a suppression
+    /// (e.g. the system call parameter of a Memcheck `Param` error).
+    pub opt_extra: Option<String>,
+    /// The kind of leak, if this is a Memcheck leak error.
+    pub opt_leak_kind: Option<LeakKind>,
+    /// The numbers of bytes and blocks leaked, if this is a Memcheck leak error read from XML
+    /// output (`<leakedbytes>` and `<leakedblocks>`).
+    pub opt_leaked: Option<(u64, u64)>,
+    /// The description of the error.
+    pub what: String,
+    /// The stack trace of the error, innermost frame first.
+    pub stack: Vec<StackFrame>,
+    /// The auxiliary descriptions of the error, e.g. where the accessed block was allocated.
+    pub aux: Vec<AuxStack>,
+    /// The name of the suppression that suppressed this error when the report was generated, or
+    /// `None` if the error was reported.
+    pub opt_suppressed_by: Option<String>,
+}
+
+/// An auxiliary description of an error, with its stack trace if any (e.g. "Address 0x51f1048 is
+/// 0 bytes after a block of size 8 alloc'd").
+#[deriving(Clone)]
+pub struct AuxStack {
+    /// The description.
+    pub what: String,
+    /// The stack trace, innermost frame first. This is empty if no stack trace was given.
+    pub stack: Vec<StackFrame>,
+}
+
+/// The HEAP SUMMARY of a Memcheck report.
+#[deriving(Clone, PartialEq, Show)]
+pub struct HeapSummary {
+    /// The number of bytes in use at exit.
+    pub in_use_bytes: u64,
+    /// The number of blocks in use at exit.
+    pub in_use_blocks: u64,
+    /// The total number of allocations.
+    pub allocs: u64,
+    /// The total number of frees.
+    pub frees: u64,
+    /// The total number of bytes allocated.
+    pub bytes_allocated: u64,
+}
+
+/// The LEAK SUMMARY of a Memcheck report.
+#[deriving(Clone, PartialEq, Show)]
+pub struct LeakSummary {
+    /// The number of bytes definitely lost.
+    pub definitely_lost_bytes: u64,
+    /// The number of blocks definitely lost.
+    pub definitely_lost_blocks: u64,
+    /// The number of bytes indirectly lost.
+    pub indirectly_lost_bytes: u64,
+    /// The number of blocks indirectly lost.
+    pub indirectly_lost_blocks: u64,
+    /// The number of bytes possibly lost.
+    pub possibly_lost_bytes: u64,
+    /// The number of blocks possibly lost.
+    pub possibly_lost_blocks: u64,
+    /// The number of bytes still reachable.
+    pub still_reachable_bytes: u64,
+    /// The number of blocks still reachable.
+    pub still_reachable_blocks: u64,
+    /// The number of bytes in suppressed loss records.
+    pub suppressed_bytes: u64,
+    /// The number of blocks in suppressed loss records.
+    pub suppressed_blocks: u64,
+}
+
+/// The ERROR SUMMARY of a report.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ErrorSummary {
+    /// The number of errors.
+    pub errors: uint,
+    /// The number of distinct errors.
+    pub contexts: uint,
+    /// The number of suppressed errors.
+    pub suppressed: uint,
+    /// The number of distinct suppressed errors.
+    pub suppressed_contexts: uint,
+}
+
+/// A suppression that was used, and the number of errors that it suppressed.
+#[deriving(Clone, PartialEq, Show)]
+pub struct UsedSuppression {
+    /// The name of the suppression.
+    pub name: String,
+    /// The number of errors that the suppression suppressed.
+    pub count: uint,
+}
+
+/// The errors and other information of a report written by Valgrind for a single process.
+#[deriving(Clone)]
+pub struct Report {
+    /// The process ID of the process, if known.
+    pub opt_pid: Option<uint>,
+    /// The process ID of the parent process, if known.
+    pub opt_ppid: Option<uint>,
+    /// The name of the tool (e.g. `memcheck`), if known.
+    pub opt_tool: Option<String>,
+    /// The reported errors, in the order in which they were reported.
+    pub errors: Vec<ValgrindError>,
+    /// The suppressions that were used.
+    pub used_suppressions: Vec<UsedSuppression>,
+    /// The HEAP SUMMARY, if reported.
+    pub opt_heap_summary: Option<HeapSummary>,
+    /// The LEAK SUMMARY, if reported.
+    pub opt_leak_summary: Option<LeakSummary>,
+    /// The ERROR SUMMARY, if reported.
+    pub opt_error_summary: Option<ErrorSummary>,
+}
+
+impl Report {
+    /// Creates a report without any information.
+    pub fn new() -> Report {
+        Report {
+            opt_pid: None,
+            opt_ppid: None,
+            opt_tool: None,
+            errors: Vec::new(),
+            used_suppressions: Vec::new(),
+            opt_heap_summary: None,
+            opt_leak_summary: None,
+            opt_error_summary: None,
+        }
+    }
+}
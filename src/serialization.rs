@@ -0,0 +1,196 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! `Encodable` and `Decodable` implementations for the suppression model, enabled by the
+//! `serialize` feature.
+//!
+//! The schema is stable, and uses the same strings as the Valgrind suppression syntax so that a
+//! serialized suppression round-trips with its text form. In JSON:
+//!
+//! * `Suppressions` is an array of `Suppression` objects.
+//! * `Suppression` is an object with the fields `"name"` (a string), `"type"` (a
+//!   `SuppressionType`), `"extra_info"` (an array of strings, or `null` if the suppression has no
+//!   extra information) and `"frames"` (an array of `Frame` strings).
+//! * `SuppressionType` is the `<tool>:<type>` string of the second line of a suppression, e.g.
+//!   `"Memcheck:Addr8"` or `"Helgrind:Race"`.
+//! * `Frame` is the calling context line, e.g. `"fun:malloc"`, `"obj:*/libc-*.so"`,
+//!   `"src:foo.c:123"` or `"..."`.
+//! * `ParseError` is an object with the fields `"kind"` (the name of the `ParseErrorKind` variant,
+//!   e.g. `"MissingType"`), `"lineno"` and `"column"` (numbers), `"path"` (a string, or `null` if
+//!   unknown), `"opening_brace_lineno"` (a number, or `null` if the error did not occur within a
+//!   suppression) and `"message"` (a string).
+//!
+//! For example:
+//!
+//! ```json
+//! [{"name":"libfoo leak","type":"Memcheck:Leak","extra_info":["match-leak-kinds: definite"],
+//!   "frames":["fun:malloc","...","obj:*/libfoo.so*"]}]
+//! ```
+
+use std::option::{Option};
+use std::result::{Result};
+use std::string::{String};
+
+use serialize::{Decodable, Decoder, Encodable, Encoder};
+
+use super::{Frame, ParseError, ParseErrorKind, Suppression, Suppressions, SuppressionType};
+use super::{BraceNotAlone, InvalidContextLine, IoFailure, MissingBrace, MissingType, NameContainsBrace, UnexpectedEof};
+
+impl<S: Encoder<E>, E> Encodable<S, E> for SuppressionType {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_str(format!("{}", self).as_slice())
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for SuppressionType {
+    fn decode(d: &mut D) -> Result<SuppressionType, E> {
+        let type_ = try!(d.read_str());
+        match type_.as_slice().find(':') {
+            None => Err(d.error(format!("invalid suppression type '{}'", type_).as_slice())),
+            Some(colon_pos) => {
+                Ok(SuppressionType::from_parts(type_.as_slice().slice_to(colon_pos), type_.as_slice().slice_from(colon_pos + 1)))
+            },
+        }
+    }
+}
+
+impl<S: Encoder<E>, E> Encodable<S, E> for Frame {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_str(format!("{}", self).as_slice())
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for Frame {
+    fn decode(d: &mut D) -> Result<Frame, E> {
+        let line = try!(d.read_str());
+        match Frame::from_line(line.as_slice()) {
+            None => Err(d.error(format!("invalid calling context line '{}'", line).as_slice())),
+            Some(frame) => Ok(frame),
+        }
+    }
+}
+
+impl<S: Encoder<E>, E> Encodable<S, E> for Suppression {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_struct("Suppression", 4, |s| {
+            try!(s.emit_struct_field("name", 0, |s| self.name.encode(s)));
+            try!(s.emit_struct_field("type", 1, |s| self.type_.encode(s)));
+            try!(s.emit_struct_field("extra_info", 2, |s| self.opt_extra_info.encode(s)));
+            s.emit_struct_field("frames", 3, |s| self.frames.encode(s))
+        })
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for Suppression {
+    fn decode(d: &mut D) -> Result<Suppression, E> {
+        d.read_struct("Suppression", 4, |d| {
+            Ok(Suppression {
+                name: try!(d.read_struct_field("name", 0, |d| Decodable::decode(d))),
+                type_: try!(d.read_struct_field("type", 1, |d| Decodable::decode(d))),
+                opt_extra_info: try!(d.read_struct_field("extra_info", 2, |d| Decodable::decode(d))),
+                frames: try!(d.read_struct_field("frames", 3, |d| Decodable::decode(d))),
+            })
+        })
+    }
+}
+
+impl<S: Encoder<E>, E> Encodable<S, E> for Suppressions {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        self.suppressions_.encode(s)
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for Suppressions {
+    fn decode(d: &mut D) -> Result<Suppressions, E> {
+        Ok(Suppressions {
+            suppressions_: try!(Decodable::decode(d)),
+        })
+    }
+}
+
+impl<S: Encoder<E>, E> Encodable<S, E> for ParseErrorKind {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_str(format!("{}", self).as_slice())
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for ParseErrorKind {
+    fn decode(d: &mut D) -> Result<ParseErrorKind, E> {
+        let kind = try!(d.read_str());
+        match kind.as_slice() {
+            "MissingBrace" => Ok(MissingBrace),
+            "BraceNotAlone" => Ok(BraceNotAlone),
+            "NameContainsBrace" => Ok(NameContainsBrace),
+            "MissingType" => Ok(MissingType),
+            "InvalidContextLine" => Ok(InvalidContextLine),
+            "UnexpectedEof" => Ok(UnexpectedEof),
+            "IoFailure" => Ok(IoFailure),
+            _ => Err(d.error(format!("invalid parse error kind '{}'", kind).as_slice())),
+        }
+    }
+}
+
+impl<S: Encoder<E>, E> Encodable<S, E> for ParseError {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_struct("ParseError", 6, |s| {
+            try!(s.emit_struct_field("kind", 0, |s| self.kind.encode(s)));
+            try!(s.emit_struct_field("lineno", 1, |s| self.lineno.encode(s)));
+            try!(s.emit_struct_field("column", 2, |s| self.column.encode(s)));
+            try!(s.emit_struct_field("path", 3, |s| {
+                self.opt_path.as_ref().map(|path| path.display().as_maybe_owned().into_string()).encode(s)
+            }));
+            try!(s.emit_struct_field("opening_brace_lineno", 4, |s| self.opt_opening_brace_lineno.encode(s)));
+            s.emit_struct_field("message", 5, |s| self.message.encode(s))
+        })
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for ParseError {
+    fn decode(d: &mut D) -> Result<ParseError, E> {
+        d.read_struct("ParseError", 6, |d| {
+            let opt_path: Option<String> = try!(d.read_struct_field("path", 3, |d| Decodable::decode(d)));
+            Ok(ParseError {
+                kind: try!(d.read_struct_field("kind", 0, |d| Decodable::decode(d))),
+                lineno: try!(d.read_struct_field("lineno", 1, |d| Decodable::decode(d))),
+                column: try!(d.read_struct_field("column", 2, |d| Decodable::decode(d))),
+                opt_path: opt_path.map(|path| Path::new(path)),
+                opt_opening_brace_lineno: try!(d.read_struct_field("opening_brace_lineno", 4, |d| Decodable::decode(d))),
+                message: try!(d.read_struct_field("message", 5, |d| Decodable::decode(d))),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader};
+
+    use serialize::json;
+
+    use super::super::{Suppressions};
+
+    #[test]
+    fn test_round_trip_other_tool() {
+        let text = "{\n   helgrind race\n   Helgrind:Race\n   fun:_ZN3foo3barEv\n   ...\n   obj:*/libpthread*.so*\n}\n";
+        let suppressions = Suppressions::parse(&mut BufReader::new(text.as_bytes())).unwrap();
+        let encoded = json::encode(&suppressions);
+        assert!(encoded.as_slice().contains("\"type\":\"Helgrind:Race\""));
+
+        let decoded: Suppressions = json::decode(encoded.as_slice()).unwrap();
+        let written = format!("{}", decoded);
+        assert_eq!(written, text.to_string());
+
+        // The written text is accepted again, with the same type.
+        let reparsed = Suppressions::parse(&mut BufReader::new(written.as_bytes())).unwrap();
+        assert!(reparsed.suppressions().next().unwrap().type_ == suppressions.suppressions().next().unwrap().type_);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Offline simulation of the effect of a set of suppressions on the errors of a Valgrind report.
+
+use std::option::{Option};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{Suppressions};
+use matching::{CompiledFrame, compile_frames, matches_compiled};
+use report::{ValgrindError};
+
+/// The number of errors suppressed by a single suppression, before and after the simulation.
+#[deriving(Clone, PartialEq, Show)]
+pub struct SuppressionCounts {
+    /// The name of the suppression.
+    pub name: String,
+    /// The number of errors that were suppressed by a suppression of this name when the report
+    /// was generated.
+    pub before: uint,
+    /// The number of errors for which this is the first matching suppression.
+    pub after: uint,
+}
+
+/// The result of simulating a set of suppressions against the errors of a report.
+#[deriving(Clone, Show)]
+pub struct Simulation {
+    /// For each error, the index of the first suppression that matches it, or `None` if the
+    /// error would be reported.
+    pub matches: Vec<Option<uint>>,
+    /// The counts of each suppression, in the order of the suppression set.
+    pub counts: Vec<SuppressionCounts>,
+    /// The indices of the errors that were reported but would now be suppressed.
+    pub newly_hidden: Vec<uint>,
+    /// The indices of the errors that were suppressed but would now be reported.
+    pub newly_exposed: Vec<uint>,
+}
+
+/// Determines, for each of `errors`, which of `suppressions` would suppress it first.
+///
+/// The `opt_suppressed_by` field of each error describes its state when the report was generated,
+/// which is compared against the simulated state to find the newly hidden and newly exposed errors.
+///
+/// The errors must come from output generated with `--demangle=no`; see `Suppression::matches()`.
+pub fn simulate(suppressions: &Suppressions, errors: &[ValgrindError]) -> Simulation {
+    let mut counts: Vec<SuppressionCounts> = suppressions.suppressions().map(|suppression| {
+            SuppressionCounts {
+                name: suppression.name.clone(),
+                before: errors.iter().filter(|error| {
+                        error.opt_suppressed_by.as_ref().map_or(false, |name| *name == suppression.name)
+                    }).count(),
+                after: 0,
+            }
+        }).collect();
+    // Each calling context is compiled once, rather than once per error.
+    let compiled: Vec<Vec<CompiledFrame>> = suppressions.suppressions().map(|suppression| {
+            compile_frames(suppression.frames.as_slice())
+        }).collect();
+    let mut matches = Vec::with_capacity(errors.len());
+    let mut newly_hidden = Vec::new();
+    let mut newly_exposed = Vec::new();
+
+    for (error_index, error) in errors.iter().enumerate() {
+        let opt_index = suppressions.suppressions().zip(compiled.iter()).position(|(suppression, frames)| {
+                matches_compiled(suppression, frames.as_slice(), error)
+            });
+        match (opt_index, error.opt_suppressed_by.is_some()) {
+            (Some(index), was_suppressed) => {
+                counts.get_mut(index).after += 1;
+                if !was_suppressed {
+                    newly_hidden.push(error_index);
+                }
+            },
+            (None, true) => newly_exposed.push(error_index),
+            (None, false) => (),
+        }
+        matches.push(opt_index);
+    }
+
+    Simulation {
+        matches: matches,
+        counts: counts,
+        newly_hidden: newly_hidden,
+        newly_exposed: newly_exposed,
+    }
+}
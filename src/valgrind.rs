@@ -11,28 +11,166 @@
 // Lesser General Public License for more details.
 
 #![crate_type = "lib"]
-#![feature(struct_variant)]
+#![feature(asm, macro_rules, struct_variant)]
 
 extern crate libc;
+#[cfg(feature = "serialize")]
+extern crate serialize;
 
+use std::collections::{Deque, RingBuf};
+use std::error::{Error};
 use std::fmt::{FormatError, Formatter, Show};
 use std::from_str::{from_str};
-use std::io::{Buffer};
+use std::io::{Buffer, EndOfFile};
+use std::mem;
 use std::option::{Option};
 use std::result::{Result, fold_};
 use std::slice::{Items};
 use std::string::{String};
 use std::vec::{Vec};
 
+use glob::{Glob};
+
+pub mod alloc;
+pub mod annotate;
+pub mod borrowed;
+pub mod builder;
+pub mod callgrind;
+pub mod client_request;
+pub mod command;
+pub mod explain;
+pub mod glob;
+pub mod helgrind;
+pub mod install;
+pub mod leak;
+pub mod load;
+pub mod log;
+pub mod log_socket;
+pub mod memcheck;
+pub mod merge;
+pub mod report;
+pub mod simulate;
+pub mod version;
+pub mod xml;
+pub mod xml_socket;
+mod matching;
+#[cfg(feature = "serialize")]
+mod serialization;
+
+/// The kind of a parse error generated while parsing a suppressions file.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum ParseErrorKind {
+    /// A line outside of a suppression is not an opening brace.
+    MissingBrace,
+    /// An opening brace is followed by other text on the same line.
+    BraceNotAlone,
+    /// The name of a suppression contains a closing brace.
+    NameContainsBrace,
+    /// The second line of a suppression is not of the form `<tool>:<type>`.
+    MissingType,
+    /// A line of a calling context is not `...`, an `obj:` line, a `fun:` line or a `src:` line.
+    InvalidContextLine,
+    /// The input ended in the middle of a suppression.
+    UnexpectedEof,
+    /// Reading the input failed.
+    IoFailure,
+}
+
 /// Holds information about a parse error generated while parsing a suppressions file.
+#[deriving(Clone, PartialEq)]
 pub struct ParseError {
+    /// The kind of parse error.
+    pub kind: ParseErrorKind,
+
     /// Line number where the parse error occurred.
     pub lineno: uint,
 
+    /// Column number (in characters, starting at 1) where the parse error occurred.
+    pub column: uint,
+
+    /// The path to the suppressions file, if known.
+    pub opt_path: Option<Path>,
+
+    /// Line number of the opening brace of the suppression in which the parse error occurred, if
+    /// the error occurred within a suppression.
+    pub opt_opening_brace_lineno: Option<uint>,
+
     /// Description of the parse error.
     pub message: String,
 }
 
+impl ParseError {
+    /// Creates a parse error of kind `kind` at line `lineno` and column `column`.
+    pub fn new(kind: ParseErrorKind, lineno: uint, column: uint, message: String) -> ParseError {
+        ParseError {
+            kind: kind,
+            lineno: lineno,
+            column: column,
+            opt_path: None,
+            opt_opening_brace_lineno: None,
+            message: message,
+        }
+    }
+
+    /// Sets the line number of the opening brace of the suppression in which the error occurred.
+    pub fn within_suppression(mut self, opening_brace_lineno: uint) -> ParseError {
+        self.opt_opening_brace_lineno = Some(opening_brace_lineno);
+        self
+    }
+
+    /// Sets the path to the suppressions file in which the error occurred.
+    pub fn with_path(mut self, path: Path) -> ParseError {
+        self.opt_path = Some(path);
+        self
+    }
+
+    /// Renders the error with the offending line of `source`, the text that was parsed, and a
+    /// caret under the offending column. If the error occurred within a suppression, the opening
+    /// line of the suppression is also shown.
+    pub fn render(&self, source: &str) -> String {
+        let line_at = |lineno: uint| -> String {
+            if lineno == 0 {
+                String::new()
+            } else {
+                source.lines().nth(lineno - 1).unwrap_or("").to_string()
+            }
+        };
+        let width = format!("{:u}", self.lineno).len();
+
+        let mut rendered = format!("{}\n", self);
+        match self.opt_opening_brace_lineno {
+            Some(opening_brace_lineno) if opening_brace_lineno != self.lineno => {
+                rendered.push_str(format!("{:>1$u} | {}\n", opening_brace_lineno, width, line_at(opening_brace_lineno)).as_slice());
+                if opening_brace_lineno + 1 < self.lineno {
+                    rendered.push_str(format!("{:>1$s} | ...\n", "", width).as_slice());
+                }
+            },
+            _ => (),
+        }
+        rendered.push_str(format!("{:>1$u} | {}\n", self.lineno, width, line_at(self.lineno)).as_slice());
+        rendered.push_str(format!("{:>1$s} | {:>2$s}^\n", "", width, "", if self.column > 0 { self.column - 1 } else { 0 }).as_slice());
+        rendered
+    }
+}
+
+impl Show for ParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        (match self.opt_path {
+            None => Ok(()),
+            Some(ref path) => write!(fmt, "{}:", path.display()),
+        })
+            .and_then(|()| -> Result<(), FormatError> {
+                write!(fmt, "{:u}:{:u}: {}", self.lineno, self.column, self.message.as_slice())
+            })
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        self.message.as_slice()
+    }
+}
+
 #[deriving(Clone)]
 pub enum Frame {
     /// A frame-level wildcard, represented by `'...'`.
@@ -50,6 +188,72 @@ pub enum Frame {
         /// Glob for the name of the function. This may contain wildcard characters `*` and `?`.
         pub glob: String,
     },
+
+    /// A source frame, e.g. `src:foo.c:123`.
+    SrcFrame {
+        /// Glob for the name of the source file. This may contain wildcard characters `*` and `?`.
+        pub glob: String,
+        /// The line number within the source file, or `None` to match any line.
+        pub opt_line: Option<uint>,
+    },
+}
+
+/// Splits the text after `src:` into the file glob and the line number, if any.
+fn split_src_line<'a>(src: &'a str) -> (&'a str, Option<uint>) {
+    match src.rfind(':') {
+        Some(colon_pos) => {
+            match from_str::<uint>(src.slice_from(colon_pos + 1)) {
+                Some(line) => (src.slice_to(colon_pos), Some(line)),
+                None => (src, None),
+            }
+        },
+        None => (src, None),
+    }
+}
+
+impl Frame {
+    /// Parses a line of a calling context: `...`, `obj:<glob>`, `fun:<glob>` or
+    /// `src:<glob>[:<line>]`. Returns `None` if `line` is not a calling context line.
+    pub fn from_line(line: &str) -> Option<Frame> {
+        let line = line.trim();
+        if line == "..." {
+            Some(FrameWildcard)
+        } else if line.starts_with("obj:") {
+            Some(ObjFrame {
+                glob: line.slice_from(4).trim_left().to_string(),
+            })
+        } else if line.starts_with("fun:") {
+            Some(FunFrame {
+                glob: line.slice_from(4).trim_left().to_string(),
+            })
+        } else if line.starts_with("src:") {
+            let (glob, opt_line) = split_src_line(line.slice_from(4).trim_left());
+            Some(SrcFrame {
+                glob: glob.to_string(),
+                opt_line: opt_line,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Compiles the glob of an object, function or source frame, or returns `None` for a
+    /// frame-level wildcard.
+    pub fn glob(&self) -> Option<Glob> {
+        match self {
+            &FrameWildcard => None,
+            &ObjFrame {
+                glob: ref glob
+            } => Some(Glob::new(glob.as_slice())),
+            &FunFrame {
+                glob: ref glob
+            } => Some(Glob::new(glob.as_slice())),
+            &SrcFrame {
+                glob: ref glob,
+                ..
+            } => Some(Glob::new(glob.as_slice())),
+        }
+    }
 }
 
 impl Show for Frame {
@@ -66,6 +270,18 @@ impl Show for Frame {
             } => {
                 write!(fmt, "fun:{}", glob.as_slice())
             },
+            &SrcFrame {
+                glob: ref glob,
+                opt_line: None,
+            } => {
+                write!(fmt, "src:{}", glob.as_slice())
+            },
+            &SrcFrame {
+                glob: ref glob,
+                opt_line: Some(line),
+            } => {
+                write!(fmt, "src:{}:{:u}", glob.as_slice(), line)
+            },
         }
     }
 }
@@ -85,6 +301,45 @@ pub enum SuppressionType {
     },
 }
 
+impl SuppressionType {
+    /// Creates the suppression type named `suppression_type` of the tool `tool_name`, as written
+    /// on the second line of a suppression (e.g. `Memcheck` and `Addr8`).
+    pub fn from_parts(tool_name: &str, suppression_type: &str) -> SuppressionType {
+        let other_type = || -> SuppressionType {
+            OtherType {
+                tool_name: tool_name.to_string(),
+                suppression_type: suppression_type.to_string(),
+            }
+        };
+        if tool_name != "Memcheck" {
+            return other_type();
+        }
+        if suppression_type.starts_with("Addr") {
+            match from_str(suppression_type.slice_from(4)) {
+                None => other_type(),
+                Some(n) => MemcheckAddr(n)
+            }
+        } else if suppression_type == "Cond" {
+            MemcheckCond
+        } else if suppression_type == "Free" {
+            MemcheckFree
+        } else if suppression_type == "Leak" {
+            MemcheckLeak
+        } else if suppression_type == "Overlap" {
+            MemcheckOverlap
+        } else if suppression_type == "Param" {
+            MemcheckParam
+        } else if suppression_type.starts_with("Value") {
+            match from_str(suppression_type.slice_from(5)) {
+                None => other_type(),
+                Some(n) => MemcheckValue(n)
+            }
+        } else {
+            other_type()
+        }
+    }
+}
+
 impl Show for SuppressionType {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
         match self {
@@ -99,7 +354,7 @@ impl Show for SuppressionType {
                 tool_name: ref tool_name,
                 suppression_type: ref suppression_type,
             } => {
-                write!(fmt, "?{}:{}", tool_name.as_slice(), suppression_type.as_slice())
+                write!(fmt, "{}:{}", tool_name.as_slice(), suppression_type.as_slice())
             },
         }
     }
@@ -154,320 +409,347 @@ pub struct Suppressions {
     suppressions_: Vec<Suppression>
 }
 
-enum ParseState {
+enum ParseState<S, F> {
     BeforeOpeningBrace,
     AfterOpeningBrace {
         opening_brace_lineno: uint,
     },
     HaveName {
         opening_brace_lineno: uint,
-        name: String,
+        name: S,
     },
     HaveSuppressionType {
         opening_brace_lineno: uint,
-        name: String,
-        tool_names: Vec<String>,
-        suppression_type: String,
+        name: S,
+        tool_names: Vec<S>,
+        suppression_type: S,
         /// Lines of extra information, used by some suppression types (e.g. a Memcheck `Param` suppression).
-        opt_extra_info: Option<Vec<String>>,
+        opt_extra_info: Option<Vec<S>>,
     },
     HaveOptExtraInfo {
         opening_brace_lineno: uint,
-        name: String,
-        tool_names: Vec<String>,
-        suppression_type: String,
-        opt_extra_info: Option<Vec<String>>,
-        frames: Vec<Frame>,
+        name: S,
+        tool_names: Vec<S>,
+        suppression_type: S,
+        opt_extra_info: Option<Vec<S>>,
+        frames: Vec<F>,
     },
 }
 
-impl Suppressions {
+/// What the parser builds suppressions from: strings of type `S` and frames of type `F`. This
+/// lets `SuppressionReader` and `borrowed::BorrowedSuppressions` share one state machine.
+trait ParseTarget<S, F> {
+    /// Returns the bytes `from` to `to` of `line`.
+    fn slice(&mut self, line: &S, from: uint, to: uint) -> S;
 
-    /// Parses the suppressions from `buf` in Valgrind suppression syntax.
-    ///
-    /// # See also
-    /// * [Suppressing errors](http://valgrind.org/docs/manual/manual-core.html#manual-core.suppress). Valgrind User Manual.
-    pub fn parse<B: Buffer>(buf: &mut B) -> Result<Suppressions, ParseError> {
-        let mut suppressions: Vec<Suppression> = Vec::new();
+    /// Parses `line` as a calling context line.
+    fn frame(&mut self, line: &S) -> Option<F>;
 
-        let mut lineno = 0u;
-        let mut state = BeforeOpeningBrace;
-        for line_res in buf.lines() {
-            match line_res {
+    /// Adds the suppressions of a complete suppression entry, one per tool.
+    fn complete(&mut self, opening_brace_lineno: uint, name: S, tool_names: Vec<S>, suppression_type: S,
+                opt_extra_info: Option<Vec<S>>, frames: Vec<F>);
+}
+
+impl ParseTarget<String, Frame> for RingBuf<(uint, Suppression)> {
+    fn slice(&mut self, line: &String, from: uint, to: uint) -> String {
+        line.as_slice().slice(from, to).to_string()
+    }
+
+    fn frame(&mut self, line: &String) -> Option<Frame> {
+        Frame::from_line(line.as_slice())
+    }
+
+    fn complete(&mut self, opening_brace_lineno: uint, name: String, tool_names: Vec<String>, suppression_type: String,
+                opt_extra_info: Option<Vec<String>>, frames: Vec<Frame>) {
+        self.extend(tool_names.iter().map(|tool_name| -> (uint, Suppression) {
+            let type_ = SuppressionType::from_parts(tool_name.as_slice(), suppression_type.as_slice());
+            (opening_brace_lineno, Suppression {
+                name: name.clone(),
+                type_: type_,
+                opt_extra_info: opt_extra_info.clone(),
+                frames: frames.clone(),
+            })
+        }));
+    }
+}
+
+/// Reads suppressions one at a time from a buffer in Valgrind suppression syntax, so that memory
+/// use is bounded by the size of the largest suppression rather than by the size of the input.
+///
+/// A suppression naming several tools (e.g. `Memcheck,Addrcheck:Cond`) yields one `Suppression`
+/// per tool. Reading stops after the first error.
+pub struct SuppressionReader<'a, B> {
+    buf: &'a mut B,
+    lineno: uint,
+    state: ParseState<String, Frame>,
+    /// Completed suppressions, with the line numbers of their opening braces.
+    completed: RingBuf<(uint, Suppression)>,
+    last_opening_brace_lineno: uint,
+    done: bool,
+}
+
+impl<'a, B: Buffer> SuppressionReader<'a, B> {
+    /// Creates a reader of the suppressions in `buf`.
+    pub fn new(buf: &'a mut B) -> SuppressionReader<'a, B> {
+        SuppressionReader {
+            buf: buf,
+            lineno: 0,
+            state: BeforeOpeningBrace,
+            completed: RingBuf::new(),
+            last_opening_brace_lineno: 0,
+            done: false,
+        }
+    }
+
+    /// The number of lines read so far.
+    pub fn lineno(&self) -> uint {
+        self.lineno
+    }
+
+    /// The line number of the opening brace of the suppression that was returned last.
+    pub fn last_opening_brace_lineno(&self) -> uint {
+        self.last_opening_brace_lineno
+    }
+}
+
+impl<'a, B: Buffer> Iterator<Result<Suppression, ParseError>> for SuppressionReader<'a, B> {
+    fn next(&mut self) -> Option<Result<Suppression, ParseError>> {
+        loop {
+            match self.completed.pop_front() {
+                Some((opening_brace_lineno, suppression)) => {
+                    self.last_opening_brace_lineno = opening_brace_lineno;
+                    return Some(Ok(suppression));
+                },
+                None => (),
+            }
+            if self.done {
+                return None;
+            }
+
+            let state = mem::replace(&mut self.state, BeforeOpeningBrace);
+            let state_res = match self.buf.read_line() {
+                    Err(ref e) if e.kind == EndOfFile => {
+                        self.done = true;
+                        check_eof(state).map(|()| BeforeOpeningBrace)
+                    },
+                    Err(e) => {
+                        Err(ParseError::new(IoFailure, self.lineno, 1, format!("IoError returned: {}", e)))
+                    },
+                    Ok(line) => {
+                        self.lineno = self.lineno + 1;
+
+                        let trimmed_line = line.as_slice().trim();
+                        if !trimmed_line.is_empty() && !trimmed_line.starts_with("#") {
+                            let column = line.as_slice().char_len() - line.as_slice().trim_left().char_len() + 1;
+                            next_state(state, self.lineno, column, trimmed_line.to_string(), &mut self.completed)
+                        } else {
+                            Ok(state)
+                        }
+                    },
+                };
+            match state_res {
+                Ok(state) => self.state = state,
                 Err(e) => {
-                    return Err(ParseError {
-                        lineno: lineno,
-                        message: format!("IoError returned: {}", e),
-                    });
+                    self.done = true;
+                    return Some(Err(e));
                 },
-                Ok(line) => {
-                    lineno = lineno + 1;
-
-                    let trimmed_line = line.as_slice().trim();
-                    if !trimmed_line.is_empty() && !trimmed_line.starts_with("#") {
-                        state = match state {
-                                BeforeOpeningBrace => {
-                                    if trimmed_line == "{" {
-                                        AfterOpeningBrace {
-                                            opening_brace_lineno: lineno
-                                        }
-                                    } else if trimmed_line.starts_with("{") {
-                                        return Err(ParseError {
-                                            lineno: lineno,
-                                            message: String::from_str("expecting an opening brace on its own line"),
-                                        });
-                                    } else {
-                                        return Err(ParseError {
-                                            lineno: lineno,
-                                            message: String::from_str("expecting an opening brace"),
-                                        });
-                                    }
-                                },
-                                AfterOpeningBrace {
-                                    opening_brace_lineno: opening_brace_lineno,
-                                } => {
-                                    // If there is a closing brace immediately after the opening brace,
-                                    // then skip this "empty" suppression (go back to the BeforeOpeningBrace
-                                    // state).
-                                    if trimmed_line == "}" {
-                                        BeforeOpeningBrace
-                                    } else if trimmed_line.contains_char('}') {
-                                        return Err(ParseError {
-                                            lineno: lineno,
-                                            message: String::from_str("the suppression name cannot contain a closing brace '}'"),
-                                        });
-                                    } else {
-                                        HaveName {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: trimmed_line.to_string(),
-                                        }
-                                    }
-                                },
-                                HaveName {
-                                    opening_brace_lineno: opening_brace_lineno,
-                                    name: name,
-                                } => {
-                                    let colon_pos = match trimmed_line.find(':') {
-                                            None => {
-                                                return Err(ParseError {
-                                                    lineno: lineno,
-                                                    message: String::from_str("no suppression type was found"),
-                                                });
-                                            }
-                                            Some(colon_pos) => colon_pos
-                                        };
-                                    let splits = trimmed_line.slice_to(colon_pos).split(',');
-                                    let tool_names: Vec<String> = splits.map(|part| part.to_string()).collect();
-                                    HaveSuppressionType {
-                                        opening_brace_lineno: opening_brace_lineno,
-                                        name: name,
-                                        tool_names: tool_names,
-                                        suppression_type: trimmed_line.slice_from(colon_pos + 1).to_string(),
-                                        opt_extra_info: None,
-                                    }
-                                },
-                                HaveSuppressionType {
-                                    opening_brace_lineno: opening_brace_lineno,
-                                    name: name,
-                                    tool_names: tool_names,
-                                    suppression_type: suppression_type,
-                                    opt_extra_info: opt_extra_info,
-                                } => {
-                                    if trimmed_line == "..." {
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: Vec::from_elem(1, FrameWildcard),
-                                        }
-                                    } else if trimmed_line.starts_with("obj:") {
-                                        let glob = trimmed_line.slice_from(4).trim_left().to_string();
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: Vec::from_elem(1, ObjFrame { glob: glob }),
-                                        }
-                                    } else if trimmed_line.starts_with("fun:") {
-                                        let glob = trimmed_line.slice_from(4).trim_left().to_string();
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: Vec::from_elem(1, ObjFrame { glob: glob }),
-                                        }
-                                    // If there is no calling context for this suppression, then skip it.
-                                    // TODO This might not be 100% correct. Perhaps some suppressions only use extra info?
-                                    } else if trimmed_line == "}" {
-                                        BeforeOpeningBrace
-                                    } else {
-                                        let extra_info = match opt_extra_info {
-                                                None => Vec::from_elem(1, trimmed_line.to_string()),
-                                                Some(mut extra_info) => {
-                                                    extra_info.push(trimmed_line.to_string());
-                                                    extra_info
-                                                }
-                                            };
-                                        HaveSuppressionType {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: Some(extra_info),
-                                        }
-                                    }
-                                },
-                                HaveOptExtraInfo {
-                                    opening_brace_lineno: opening_brace_lineno,
-                                    name: name,
-                                    tool_names: tool_names,
-                                    suppression_type: suppression_type,
-                                    opt_extra_info: opt_extra_info,
-                                    frames: mut frames,
-                                } => {
-                                    if trimmed_line == "..." {
-                                        frames.push(FrameWildcard);
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: frames,
-                                        }
-                                    } else if trimmed_line.starts_with("obj:") {
-                                        frames.push(ObjFrame {
-                                            glob: trimmed_line.slice_from(4).trim_left().to_string(),
-                                        });
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: frames,
-                                        }
-                                    } else if trimmed_line.starts_with("fun:") {
-                                        frames.push(FunFrame {
-                                            glob: trimmed_line.slice_from(4).trim_left().to_string(),
-                                        });
-                                        HaveOptExtraInfo {
-                                            opening_brace_lineno: opening_brace_lineno,
-                                            name: name,
-                                            tool_names: tool_names,
-                                            suppression_type: suppression_type,
-                                            opt_extra_info: opt_extra_info,
-                                            frames: frames,
-                                        }
-                                    } else if trimmed_line == "}" {
-
-                                        suppressions.extend(tool_names.iter().map(|tool_name| -> Suppression {
-                                            let type_ = if tool_name.as_slice() == "Memcheck" {
-                                                    if suppression_type.as_slice().starts_with("Addr") {
-                                                        match from_str(suppression_type.as_slice().slice_from(4)) {
-                                                            None => OtherType {
-                                                                tool_name: tool_name.to_string(),
-                                                                suppression_type: suppression_type.clone(),
-                                                            },
-                                                            Some(n) => MemcheckAddr(n)
-                                                        }
-                                                    } else if suppression_type.as_slice() == "Cond" {
-                                                        MemcheckCond
-                                                    } else if suppression_type.as_slice() == "Free" {
-                                                        MemcheckFree
-                                                    } else if suppression_type.as_slice() == "Leak" {
-                                                        MemcheckLeak
-                                                    } else if suppression_type.as_slice() == "Overlap" {
-                                                        MemcheckOverlap
-                                                    } else if suppression_type.as_slice() == "Param" {
-                                                        MemcheckParam
-                                                    } else if suppression_type.as_slice().starts_with("Value") {
-                                                        match from_str(suppression_type.as_slice().slice_from(5)) {
-                                                            None => OtherType {
-                                                                tool_name: tool_name.to_string(),
-                                                                suppression_type: suppression_type.clone(),
-                                                            },
-                                                            Some(n) => MemcheckValue(n)
-                                                        }
-                                                    } else {
-                                                        OtherType {
-                                                            tool_name: tool_name.to_string(),
-                                                            suppression_type: suppression_type.clone(),
-                                                        }
-                                                    }
-                                                } else {
-                                                    OtherType {
-                                                        tool_name: tool_name.to_string(),
-                                                        suppression_type: suppression_type.clone(),
-                                                    }
-                                                };
-                                            Suppression {
-                                                name: name.clone(),
-                                                type_: type_,
-                                                opt_extra_info: opt_extra_info.clone(),
-                                                frames: frames.clone(),
-                                            }
-                                        }));
-
-                                        BeforeOpeningBrace
-                                    } else {
-                                        return Err(ParseError {
-                                            lineno: lineno,
-                                            message: String::from_str("invalid calling context line"),
-                                        });
-                                    }
-                                },
-                            }; // end match state
-                    }
-                }, // end Ok(line)
             }
         }
-        match state {
+    }
+}
+
+/// Returns the parse state after the non-empty, non-comment line `trimmed_line`, which starts at
+/// column `column`, passing the suppressions completed by the line to `target`.
+fn next_state<S: Str, F, T: ParseTarget<S, F>>(state: ParseState<S, F>, lineno: uint, column: uint, trimmed_line: S,
+                                               target: &mut T) -> Result<ParseState<S, F>, ParseError> {
+    let is_closing_brace = trimmed_line.as_slice() == "}";
+    Ok(match state {
+            BeforeOpeningBrace => {
+                let line = trimmed_line.as_slice();
+                if line == "{" {
+                    AfterOpeningBrace {
+                        opening_brace_lineno: lineno
+                    }
+                } else if line.starts_with("{") {
+                    return Err(ParseError::new(BraceNotAlone, lineno, column + 1,
+                                               String::from_str("expecting an opening brace on its own line")));
+                } else {
+                    return Err(ParseError::new(MissingBrace, lineno, column,
+                                               String::from_str("expecting an opening brace")));
+                }
+            },
             AfterOpeningBrace {
                 opening_brace_lineno: opening_brace_lineno,
-                ..
             } => {
-                return Err(ParseError {
-                    lineno: opening_brace_lineno,
-                    message: String::from_str("unexpectedly encountered EOF while parsing a suppression"),
-                });
+                let opt_brace_column = {
+                        let line = trimmed_line.as_slice();
+                        line.find('}').map(|brace_pos| column + line.slice_to(brace_pos).char_len())
+                    };
+                // If there is a closing brace immediately after the opening brace,
+                // then skip this "empty" suppression (go back to the BeforeOpeningBrace
+                // state).
+                if is_closing_brace {
+                    BeforeOpeningBrace
+                } else if opt_brace_column.is_some() {
+                    return Err(ParseError::new(NameContainsBrace, lineno, opt_brace_column.unwrap(),
+                                               String::from_str("the suppression name cannot contain a closing brace '}'"))
+                               .within_suppression(opening_brace_lineno));
+                } else {
+                    HaveName {
+                        opening_brace_lineno: opening_brace_lineno,
+                        name: trimmed_line,
+                    }
+                }
             },
             HaveName {
                 opening_brace_lineno: opening_brace_lineno,
                 name: name,
             } => {
-                return Err(ParseError {
-                    lineno: opening_brace_lineno,
-                    message: format!("unexpectedly encountered EOF while parsing the suppression named '{}'", name.as_slice()),
-                });
+                let colon_pos = match trimmed_line.as_slice().find(':') {
+                        None => {
+                            return Err(ParseError::new(MissingType, lineno, column,
+                                                       String::from_str("no suppression type was found"))
+                                       .within_suppression(opening_brace_lineno));
+                        }
+                        Some(colon_pos) => colon_pos
+                    };
+                let mut tool_names = Vec::new();
+                let mut start = 0;
+                loop {
+                    match trimmed_line.as_slice().slice(start, colon_pos).find(',') {
+                        None => {
+                            tool_names.push(target.slice(&trimmed_line, start, colon_pos));
+                            break;
+                        },
+                        Some(comma_pos) => {
+                            tool_names.push(target.slice(&trimmed_line, start, start + comma_pos));
+                            start = start + comma_pos + 1;
+                        },
+                    }
+                }
+                let len = trimmed_line.as_slice().len();
+                HaveSuppressionType {
+                    opening_brace_lineno: opening_brace_lineno,
+                    name: name,
+                    tool_names: tool_names,
+                    suppression_type: target.slice(&trimmed_line, colon_pos + 1, len),
+                    opt_extra_info: None,
+                }
             },
             HaveSuppressionType {
                 opening_brace_lineno: opening_brace_lineno,
                 name: name,
-                ..
+                tool_names: tool_names,
+                suppression_type: suppression_type,
+                opt_extra_info: opt_extra_info,
             } => {
-                return Err(ParseError {
-                    lineno: opening_brace_lineno,
-                    message: format!("unexpectedly encountered EOF while parsing the suppression named '{}'", name.as_slice()),
-                });
+                let opt_frame = target.frame(&trimmed_line);
+                match opt_frame {
+                    Some(frame) => {
+                        HaveOptExtraInfo {
+                            opening_brace_lineno: opening_brace_lineno,
+                            name: name,
+                            tool_names: tool_names,
+                            suppression_type: suppression_type,
+                            opt_extra_info: opt_extra_info,
+                            frames: vec!(frame),
+                        }
+                    },
+                    // If there is no calling context for this suppression, then skip it.
+                    // TODO This might not be 100% correct. Perhaps some suppressions only use extra info?
+                    None if is_closing_brace => BeforeOpeningBrace,
+                    None => {
+                        let extra_info = match opt_extra_info {
+                                None => vec!(trimmed_line),
+                                Some(mut extra_info) => {
+                                    extra_info.push(trimmed_line);
+                                    extra_info
+                                }
+                            };
+                        HaveSuppressionType {
+                            opening_brace_lineno: opening_brace_lineno,
+                            name: name,
+                            tool_names: tool_names,
+                            suppression_type: suppression_type,
+                            opt_extra_info: Some(extra_info),
+                        }
+                    },
+                }
             },
             HaveOptExtraInfo {
                 opening_brace_lineno: opening_brace_lineno,
                 name: name,
-                ..
+                tool_names: tool_names,
+                suppression_type: suppression_type,
+                opt_extra_info: opt_extra_info,
+                frames: mut frames,
             } => {
-                return Err(ParseError {
-                    lineno: opening_brace_lineno,
-                    message: format!("unexpectedly encountered EOF while parsing the suppression named '{}'", name.as_slice()),
-                });
+                let opt_frame = target.frame(&trimmed_line);
+                match opt_frame {
+                    Some(frame) => {
+                        frames.push(frame);
+                        HaveOptExtraInfo {
+                            opening_brace_lineno: opening_brace_lineno,
+                            name: name,
+                            tool_names: tool_names,
+                            suppression_type: suppression_type,
+                            opt_extra_info: opt_extra_info,
+                            frames: frames,
+                        }
+                    },
+                    None if is_closing_brace => {
+                        target.complete(opening_brace_lineno, name, tool_names, suppression_type, opt_extra_info, frames);
+                        BeforeOpeningBrace
+                    },
+                    None => {
+                        return Err(ParseError::new(InvalidContextLine, lineno, column,
+                                                   String::from_str("invalid calling context line"))
+                                   .within_suppression(opening_brace_lineno));
+                    },
+                }
             },
-            _ => (),
+    }) // end match state
+}
+
+/// Checks that `state`, the parse state at EOF, is not in the middle of a suppression.
+fn check_eof<S: Str, F>(state: ParseState<S, F>) -> Result<(), ParseError> {
+    let (opening_brace_lineno, opt_name) = match state {
+            BeforeOpeningBrace => return Ok(()),
+            AfterOpeningBrace {
+                opening_brace_lineno: opening_brace_lineno,
+            } => (opening_brace_lineno, None),
+            HaveName {
+                opening_brace_lineno: opening_brace_lineno,
+                name: name,
+            } => (opening_brace_lineno, Some(name)),
+            HaveSuppressionType {
+                opening_brace_lineno: opening_brace_lineno,
+                name: name,
+                ..
+            } => (opening_brace_lineno, Some(name)),
+            HaveOptExtraInfo {
+                opening_brace_lineno: opening_brace_lineno,
+                name: name,
+                ..
+            } => (opening_brace_lineno, Some(name)),
+        };
+    let message = match opt_name {
+            None => String::from_str("unexpectedly encountered EOF while parsing a suppression"),
+            Some(name) => format!("unexpectedly encountered EOF while parsing the suppression named '{}'", name.as_slice()),
+        };
+    Err(ParseError::new(UnexpectedEof, opening_brace_lineno, 1, message).within_suppression(opening_brace_lineno))
+}
+
+impl Suppressions {
+
+    /// Parses the suppressions from `buf` in Valgrind suppression syntax.
+    ///
+    /// # See also
+    /// * [Suppressing errors](http://valgrind.org/docs/manual/manual-core.html#manual-core.suppress). Valgrind User Manual.
+    pub fn parse<B: Buffer>(buf: &mut B) -> Result<Suppressions, ParseError> {
+        let mut suppressions: Vec<Suppression> = Vec::new();
+        for suppression_res in SuppressionReader::new(buf) {
+            suppressions.push(try!(suppression_res));
         }
 
         Ok(Suppressions {
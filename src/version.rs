@@ -0,0 +1,195 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Detection of the Valgrind version and of the suppression features that it supports.
+
+use std::fmt::{FormatError, Formatter, Show};
+use std::from_str::{from_str};
+use std::io::{IoError};
+use std::io::process::{Command};
+use std::option::{Option};
+use std::result::{Result};
+use std::str;
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{Frame, FrameWildcard, ObjFrame, FunFrame, SrcFrame, MemcheckAddr, MemcheckValue, OtherType,
+            Suppression, SuppressionType, Suppressions};
+
+/// A Valgrind version, e.g. 3.10.0. Versions compare by major, then minor, then patch number.
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version number, e.g. 3.
+    pub major: uint,
+    /// The minor version number, e.g. 10.
+    pub minor: uint,
+    /// The patch number, e.g. 0.
+    pub patch: uint,
+}
+
+/// An error generated while querying the version of Valgrind.
+pub enum VersionError {
+    /// Running `valgrind --version` failed.
+    VersionIoError(IoError),
+    /// The output of `valgrind --version` was not recognized.
+    UnrecognizedVersion(String),
+}
+
+impl Show for VersionError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        match *self {
+            VersionIoError(ref e) => write!(fmt, "{}", e),
+            UnrecognizedVersion(ref output) => write!(fmt, "unrecognized version: {}", output.as_slice()),
+        }
+    }
+}
+
+/// A feature of a suppression that the Valgrind version does not support.
+#[deriving(Clone, PartialEq)]
+pub struct CompatWarning {
+    /// The index of the suppression within its set of suppressions.
+    pub index: uint,
+    /// The name of the suppression.
+    pub name: String,
+    /// A description of the unsupported feature.
+    pub message: String,
+}
+
+impl Show for CompatWarning {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "suppression `{}`: {}", self.name.as_slice(), self.message.as_slice())
+    }
+}
+
+impl Version {
+    /// Creates the version `major.minor.patch`.
+    pub fn new(major: uint, minor: uint, patch: uint) -> Version {
+        Version {
+            major: major,
+            minor: minor,
+            patch: patch,
+        }
+    }
+
+    /// Parses the output of `valgrind --version` (e.g. `valgrind-3.10.0`) or a bare version number.
+    /// A missing patch number is taken as 0 and a suffix such as `.SVN` is ignored.
+    pub fn parse(s: &str) -> Option<Version> {
+        let s = s.trim();
+        let s = if s.starts_with("valgrind-") { s.slice_from(9) } else { s };
+        let numbers: Vec<Option<uint>> = s.split('.').take(3).map(|part| from_str(part)).collect();
+        match numbers.as_slice() {
+            [Some(major), Some(minor)] => Some(Version::new(major, minor, 0)),
+            [Some(major), Some(minor), Some(patch)] => Some(Version::new(major, minor, patch)),
+            [Some(major), Some(minor), None] => Some(Version::new(major, minor, 0)),
+            _ => None,
+        }
+    }
+
+    /// Runs `valgrind --version` with the `valgrind` executable at `valgrind` and parses its
+    /// output.
+    pub fn query(valgrind: &Path) -> Result<Version, VersionError> {
+        let output = try!(Command::new(valgrind).arg("--version").output().map_err(VersionIoError));
+        let stdout = String::from_str(str::from_utf8_lossy(output.output.as_slice()).as_slice());
+        match Version::parse(stdout.as_slice()) {
+            None => Err(UnrecognizedVersion(stdout)),
+            Some(version) => Ok(version),
+        }
+    }
+
+    /// Returns whether this version accepts suppressions of type `type_`. Types of tools other
+    /// than Memcheck, Helgrind, DRD and SGCheck are assumed to be supported.
+    pub fn supports_type(&self, type_: &SuppressionType) -> bool {
+        match *type_ {
+            MemcheckAddr(n) | MemcheckValue(n) => {
+                match n {
+                    1 | 2 | 4 | 8 => true,
+                    16 => *self >= Version::new(3, 3, 0),
+                    32 => *self >= Version::new(3, 8, 0),
+                    _ => false,
+                }
+            },
+            OtherType {
+                tool_name: ref tool_name,
+                ..
+            } => {
+                match tool_name.as_slice() {
+                    "Helgrind" | "drd" => *self >= Version::new(3, 3, 0),
+                    "SGCheck" => *self >= Version::new(3, 7, 0),
+                    _ => true,
+                }
+            },
+            _ => true,
+        }
+    }
+
+    /// Returns whether this version accepts the calling context frame `frame`.
+    pub fn supports_frame(&self, frame: &Frame) -> bool {
+        match *frame {
+            FrameWildcard => *self >= Version::new(3, 4, 0),
+            ObjFrame { .. } | FunFrame { .. } => true,
+            SrcFrame { .. } => *self >= Version::new(3, 14, 0),
+        }
+    }
+
+    /// Returns whether this version accepts the `match-leak-kinds:` line of `Memcheck:Leak`
+    /// suppressions.
+    pub fn supports_match_leak_kinds(&self) -> bool {
+        *self >= Version::new(3, 9, 0)
+    }
+
+    /// Returns whether this version writes version 4 of the XML output protocol.
+    pub fn supports_xml_protocol_4(&self) -> bool {
+        *self >= Version::new(3, 5, 0)
+    }
+
+    /// Adds a warning to `warnings` for each feature of `suppression` that this version does not
+    /// support.
+    fn check_suppression(&self, index: uint, suppression: &Suppression, warnings: &mut Vec<CompatWarning>) {
+        let mut warn = |message: String| {
+            warnings.push(CompatWarning {
+                index: index,
+                name: suppression.name.clone(),
+                message: message,
+            });
+        };
+        if !self.supports_type(&suppression.type_) {
+            warn(format!("the suppression type {} is not supported by Valgrind {}", suppression.type_, *self));
+        }
+        match suppression.opt_extra_info {
+            Some(ref extra_info) if !self.supports_match_leak_kinds() => {
+                if extra_info.iter().any(|line| line.as_slice().starts_with("match-leak-kinds:")) {
+                    warn(format!("`match-leak-kinds:` is not supported by Valgrind {}", *self));
+                }
+            },
+            _ => (),
+        }
+        match suppression.frames.iter().find(|frame| !self.supports_frame(*frame)) {
+            Some(frame) => warn(format!("the frame `{}` is not supported by Valgrind {}", *frame, *self)),
+            None => (),
+        }
+    }
+
+    /// Checks `suppressions` for features that this version does not support.
+    pub fn check_suppressions(&self, suppressions: &Suppressions) -> Vec<CompatWarning> {
+        let mut warnings = Vec::new();
+        for (index, suppression) in suppressions.suppressions().enumerate() {
+            self.check_suppression(index, suppression, &mut warnings);
+        }
+        warnings
+    }
+}
+
+impl Show for Version {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "{:u}.{:u}.{:u}", self.major, self.minor, self.patch)
+    }
+}
@@ -0,0 +1,537 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Parsing of the XML output of Valgrind (`--xml=yes`).
+//!
+//! Only the subset of XML written by Valgrind is supported: elements, attributes, character data,
+//! the predefined and numeric character references, CDATA sections, comments, processing
+//! instructions and document type declarations (the last three are skipped).
+//!
+//! # See also
+//! * [Valgrind XML output: protocol version 4](http://valgrind.org/docs/manual/manual-core.html#manual-core.xml). Valgrind User Manual and `docs/internals/xml-output-protocol4.txt`.
+
+use std::char;
+use std::fmt::{FormatError, Formatter, Show};
+use std::from_str::{from_str};
+use std::num::{from_str_radix};
+use std::option::{Option};
+use std::result::{Result};
+use std::slice::{Items};
+use std::string::{String};
+use std::vec::{Vec};
+
+use super::{SuppressionType};
+use report::{DefiniteLeak, IndirectLeak, PossibleLeak, ReachableLeak};
+use report::{AuxStack, Report, StackFrame, UsedSuppression, ValgrindError};
+
+/// Holds information about an error generated while parsing XML.
+#[deriving(Clone, PartialEq)]
+pub struct XmlError {
+    /// The byte offset in the input at which the error occurred.
+    pub offset: uint,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl Show for XmlError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        write!(fmt, "offset {:u}: {}", self.offset, self.message.as_slice())
+    }
+}
+
+/// A node of an XML document.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Node {
+    /// An element.
+    ElementNode(Element),
+    /// Character data, with character references replaced.
+    TextNode(String),
+}
+
+/// An element of an XML document.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Element {
+    /// The name of the element.
+    pub name: String,
+    /// The attributes of the element, in document order.
+    pub attributes: Vec<(String, String)>,
+    /// The children of the element, in document order.
+    pub children: Vec<Node>,
+}
+
+/// An iterator over the child elements of an element.
+pub struct Elements<'a> {
+    nodes: Items<'a, Node>,
+}
+
+impl<'a> Iterator<&'a Element> for Elements<'a> {
+    fn next(&mut self) -> Option<&'a Element> {
+        loop {
+            match self.nodes.next() {
+                None => return None,
+                Some(&ElementNode(ref element)) => return Some(element),
+                Some(&TextNode(_)) => (),
+            }
+        }
+    }
+}
+
+impl Element {
+    /// Returns an iterator over the child elements.
+    pub fn elements<'a>(&'a self) -> Elements<'a> {
+        Elements {
+            nodes: self.children.iter(),
+        }
+    }
+
+    /// Returns the first child element named `name`.
+    pub fn child<'a>(&'a self, name: &str) -> Option<&'a Element> {
+        self.elements().find(|element| element.name.as_slice() == name)
+    }
+
+    /// Returns the child elements named `name`.
+    pub fn children_named<'a>(&'a self, name: &str) -> Vec<&'a Element> {
+        self.elements().filter(|element| element.name.as_slice() == name).collect()
+    }
+
+    /// Returns the concatenated character data of this element and its descendants.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in self.children.iter() {
+            match *node {
+                ElementNode(ref element) => text.push_str(element.text().as_slice()),
+                TextNode(ref s) => text.push_str(s.as_slice()),
+            }
+        }
+        text
+    }
+
+    /// Returns the trimmed text of the first child element named `name`.
+    pub fn child_text(&self, name: &str) -> Option<String> {
+        self.child(name).map(|element| element.text().as_slice().trim().to_string())
+    }
+}
+
+/// A token of an XML document.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Token {
+    /// A start tag, with the element name, the attributes and whether the tag is self-closing.
+    StartTag(String, Vec<(String, String)>, bool),
+    /// An end tag, with the element name.
+    EndTag(String),
+    /// Character data, with character references replaced.
+    Text(String),
+}
+
+/// Splits XML text into tokens.
+pub struct Tokenizer<'a> {
+    text: &'a str,
+    pos: uint,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a tokenizer of `text`.
+    pub fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            text: text,
+            pos: 0,
+        }
+    }
+
+    /// The byte offset of the next token.
+    pub fn pos(&self) -> uint {
+        self.pos
+    }
+
+    fn error(&self, message: String) -> XmlError {
+        XmlError {
+            offset: self.pos,
+            message: message,
+        }
+    }
+
+    /// Skips past the next occurrence of `end`.
+    fn skip_past(&mut self, end: &str) -> Result<(), XmlError> {
+        match self.text.slice_from(self.pos).find_str(end) {
+            None => Err(self.error(format!("unterminated markup; expecting '{}'", end))),
+            Some(end_pos) => {
+                self.pos = self.pos + end_pos + end.len();
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns the next token, or `None` at the end of the text. Comments, processing
+    /// instructions and document type declarations are skipped.
+    pub fn next_token(&mut self) -> Result<Option<Token>, XmlError> {
+        loop {
+            let rest = self.text.slice_from(self.pos);
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            if !rest.starts_with("<") {
+                let end_pos = rest.find('<').unwrap_or(rest.len());
+                let text = try!(unescape(rest.slice_to(end_pos)).map_err(|message| self.error(message)));
+                self.pos = self.pos + end_pos;
+                return Ok(Some(Text(text)));
+            }
+            if rest.starts_with("<!--") {
+                try!(self.skip_past("-->"));
+            } else if rest.starts_with("<![CDATA[") {
+                let end_pos = match rest.find_str("]]>") {
+                        None => return Err(self.error(String::from_str("unterminated CDATA section"))),
+                        Some(end_pos) => end_pos,
+                    };
+                self.pos = self.pos + end_pos + 3;
+                return Ok(Some(Text(rest.slice(9, end_pos).to_string())));
+            } else if rest.starts_with("<?") {
+                try!(self.skip_past("?>"));
+            } else if rest.starts_with("<!") {
+                try!(self.skip_past(">"));
+            } else {
+                let end_pos = match rest.find('>') {
+                        None => return Err(self.error(String::from_str("unterminated tag"))),
+                        Some(end_pos) => end_pos,
+                    };
+                let tag = rest.slice(1, end_pos);
+                let token = try!(parse_tag(tag).map_err(|message| self.error(message)));
+                self.pos = self.pos + end_pos + 1;
+                return Ok(Some(token));
+            }
+        }
+    }
+}
+
+/// Parses the contents of a tag, between `<` and `>`.
+fn parse_tag(tag: &str) -> Result<Token, String> {
+    if tag.starts_with("/") {
+        return Ok(EndTag(tag.slice_from(1).trim().to_string()));
+    }
+    let (tag, self_closing) = if tag.ends_with("/") {
+            (tag.slice_to(tag.len() - 1), true)
+        } else {
+            (tag, false)
+        };
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = tag.slice_to(name_end);
+    if name.is_empty() {
+        return Err(String::from_str("missing element name"));
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = tag.slice_from(name_end).trim_left();
+    while !rest.is_empty() {
+        let eq_pos = match rest.find('=') {
+                None => return Err(format!("invalid attribute in tag '{}'", name)),
+                Some(eq_pos) => eq_pos,
+            };
+        let attr_name = rest.slice_to(eq_pos).trim().to_string();
+        let value_rest = rest.slice_from(eq_pos + 1).trim_left();
+        let quote = match value_rest.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(format!("unquoted value of attribute '{}'", attr_name)),
+            };
+        let value_end = match value_rest.slice_from(1).find(quote) {
+                None => return Err(format!("unterminated value of attribute '{}'", attr_name)),
+                Some(value_end) => value_end + 1,
+            };
+        attributes.push((attr_name, try!(unescape(value_rest.slice(1, value_end)))));
+        rest = value_rest.slice_from(value_end + 1).trim_left();
+    }
+    Ok(StartTag(name.to_string(), attributes, self_closing))
+}
+
+/// Replaces the character references in `s`.
+pub fn unescape(s: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    loop {
+        let amp_pos = match rest.find('&') {
+                None => break,
+                Some(amp_pos) => amp_pos,
+            };
+        unescaped.push_str(rest.slice_to(amp_pos));
+        let semicolon_pos = match rest.slice_from(amp_pos).find(';') {
+                None => return Err(String::from_str("unterminated character reference")),
+                Some(semicolon_pos) => amp_pos + semicolon_pos,
+            };
+        let reference = rest.slice(amp_pos + 1, semicolon_pos);
+        let c = match reference {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if reference.starts_with("#x") => {
+                    from_str_radix::<u32>(reference.slice_from(2), 16).and_then(char::from_u32)
+                },
+                _ if reference.starts_with("#") => {
+                    from_str::<u32>(reference.slice_from(1)).and_then(char::from_u32)
+                },
+                _ => None,
+            };
+        match c {
+            None => return Err(format!("invalid character reference '&{};'", reference)),
+            Some(c) => unescaped.push_char(c),
+        }
+        rest = rest.slice_from(semicolon_pos + 1);
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+/// Parses `text` as an XML document and returns its root element.
+pub fn parse_document(text: &str) -> Result<Element, XmlError> {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut stack: Vec<Element> = Vec::new();
+    loop {
+        let pos = tokenizer.pos();
+        let token = match try!(tokenizer.next_token()) {
+                None => break,
+                Some(token) => token,
+            };
+        match token {
+            StartTag(name, attributes, self_closing) => {
+                let element = Element {
+                    name: name,
+                    attributes: attributes,
+                    children: Vec::new(),
+                };
+                if !self_closing {
+                    stack.push(element);
+                } else if stack.is_empty() {
+                    return Ok(element);
+                } else {
+                    stack.mut_last().unwrap().children.push(ElementNode(element));
+                }
+            },
+            EndTag(name) => {
+                let element = match stack.pop() {
+                        Some(element) if element.name == name => element,
+                        _ => {
+                            return Err(XmlError {
+                                offset: pos,
+                                message: format!("unexpected end tag '{}'", name),
+                            });
+                        },
+                    };
+                if stack.is_empty() {
+                    return Ok(element);
+                }
+                stack.mut_last().unwrap().children.push(ElementNode(element));
+            },
+            Text(text) => {
+                match stack.mut_last() {
+                    Some(parent) => parent.children.push(TextNode(text)),
+                    None if text.as_slice().trim().is_empty() => (),
+                    None => {
+                        return Err(XmlError {
+                            offset: pos,
+                            message: String::from_str("character data outside of the root element"),
+                        });
+                    },
+                }
+            },
+        }
+    }
+    Err(XmlError {
+        offset: text.len(),
+        message: String::from_str("unexpectedly encountered EOF while parsing the document"),
+    })
+}
+
+/// Converts the name of a tool in the XML output (e.g. `memcheck`) to the tool name used by
+/// suppressions (e.g. `Memcheck`).
+pub fn suppression_tool_name(tool: &str) -> String {
+    match tool {
+        "memcheck" => String::from_str("Memcheck"),
+        "helgrind" => String::from_str("Helgrind"),
+        "exp-sgcheck" => String::from_str("SGCheck"),
+        _ => tool.to_string(),
+    }
+}
+
+/// Returns the number following `prefix` in `what`, e.g. the size in "Invalid read of size 8".
+pub fn number_after(what: &str, prefix: &str) -> Option<uint> {
+    what.find_str(prefix).and_then(|pos| {
+        let rest = what.slice_from(pos + prefix.len());
+        let end = rest.find(|c: char| !c.is_digit()).unwrap_or(rest.len());
+        from_str(rest.slice_to(end))
+    })
+}
+
+/// Returns the system call parameter of a "Syscall param write(buf) points to ..." description.
+pub fn syscall_param(what: &str) -> Option<String> {
+    if !what.starts_with("Syscall param ") {
+        return None;
+    }
+    let rest = what.slice_from(14);
+    Some(rest.slice_to(rest.find(' ').unwrap_or(rest.len())).to_string())
+}
+
+/// Converts a `<frame>` element to a stack frame.
+pub fn stack_frame_from_xml(frame: &Element) -> StackFrame {
+    StackFrame {
+        opt_ip: frame.child_text("ip").and_then(|ip| {
+            let ip = ip.as_slice();
+            from_str_radix::<u64>(if ip.starts_with("0x") { ip.slice_from(2) } else { ip }, 16)
+        }),
+        opt_obj: frame.child_text("obj"),
+        opt_fn_name: frame.child_text("fn"),
+        opt_dir: frame.child_text("dir"),
+        opt_file: frame.child_text("file"),
+        opt_line: frame.child_text("line").and_then(|line| from_str(line.as_slice())),
+    }
+}
+
+/// Returns the description of an `<error>` element: the text of `<what>`, or of `<xwhat><text>`.
+pub fn error_what(error: &Element) -> String {
+    error.child_text("what")
+        .or_else(|| error.child("xwhat").and_then(|xwhat| xwhat.child_text("text")))
+        .unwrap_or(String::new())
+}
+
+/// Returns the stack traces of an `<error>` element: the first `<stack>`, and the auxiliary
+/// descriptions given by `<auxwhat>` (or `<xauxwhat>`), each with the `<stack>` that follows it.
+fn error_stacks(error: &Element) -> (Vec<StackFrame>, Vec<AuxStack>) {
+    let mut opt_stack = None;
+    let mut aux: Vec<AuxStack> = Vec::new();
+    for child in error.elements() {
+        match child.name.as_slice() {
+            "auxwhat" | "xauxwhat" => {
+                let what = if child.name.as_slice() == "auxwhat" {
+                        child.text()
+                    } else {
+                        child.child_text("text").unwrap_or(String::new())
+                    };
+                aux.push(AuxStack {
+                    what: what,
+                    stack: Vec::new(),
+                });
+            },
+            "stack" => {
+                let frames: Vec<StackFrame> = child.children_named("frame").iter().map(|frame| stack_frame_from_xml(*frame)).collect();
+                if opt_stack.is_none() {
+                    opt_stack = Some(frames);
+                } else {
+                    match aux.mut_last() {
+                        Some(last) => last.stack = frames,
+                        None => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    (opt_stack.unwrap_or(Vec::new()), aux)
+}
+
+/// Converts an `<error>` element of the output of the tool `tool` (e.g. `memcheck`) to an error.
+pub fn error_from_xml(tool: &str, error: &Element) -> ValgrindError {
+    let what = error_what(error);
+    let kind = error.child_text("kind").unwrap_or(String::new());
+    let tool_name = suppression_tool_name(tool);
+    let mut opt_extra = None;
+    let mut opt_leak_kind = None;
+    let type_ = if tool_name.as_slice() == "Memcheck" {
+            let kind = kind.as_slice();
+            let memcheck_type = match kind {
+                    "InvalidRead" | "InvalidWrite" => number_after(what.as_slice(), "of size ").map(|n| format!("Addr{:u}", n)),
+                    "UninitValue" => number_after(what.as_slice(), "of size ").map(|n| format!("Value{:u}", n)),
+                    "UninitCondition" => Some(String::from_str("Cond")),
+                    "InvalidFree" | "MismatchedFree" => Some(String::from_str("Free")),
+                    "Overlap" => Some(String::from_str("Overlap")),
+                    "SyscallParam" => {
+                        opt_extra = syscall_param(what.as_slice());
+                        Some(String::from_str("Param"))
+                    },
+                    "InvalidJump" => Some(String::from_str("Jump")),
+                    _ if kind.starts_with("Leak_") => {
+                        opt_leak_kind = match kind {
+                                "Leak_DefinitelyLost" => Some(DefiniteLeak),
+                                "Leak_IndirectlyLost" => Some(IndirectLeak),
+                                "Leak_PossiblyLost" => Some(PossibleLeak),
+                                "Leak_StillReachable" => Some(ReachableLeak),
+                                _ => None,
+                            };
+                        Some(String::from_str("Leak"))
+                    },
+                    _ => None,
+                };
+            SuppressionType::from_parts("Memcheck", memcheck_type.unwrap_or(kind.to_string()).as_slice())
+        } else {
+            SuppressionType::from_parts(tool_name.as_slice(), kind.as_slice())
+        };
+    let (stack, aux) = error_stacks(error);
+    let opt_leaked = error.child("xwhat").and_then(|xwhat| {
+        let count = |name: &str| xwhat.child_text(name).and_then(|count| from_str::<u64>(count.as_slice()));
+        match (count("leakedbytes"), count("leakedblocks")) {
+            (Some(bytes), Some(blocks)) => Some((bytes, blocks)),
+            _ => None,
+        }
+    });
+
+    ValgrindError {
+        opt_unique: error.child_text("unique").and_then(|unique| {
+            let unique = unique.as_slice();
+            from_str_radix::<u64>(if unique.starts_with("0x") { unique.slice_from(2) } else { unique }, 16)
+        }),
+        type_: type_,
+        opt_extra: opt_extra,
+        opt_leak_kind: opt_leak_kind,
+        opt_leaked: opt_leaked,
+        what: what,
+        stack: stack,
+        aux: aux,
+        opt_suppressed_by: None,
+    }
+}
+
+/// Converts a `<suppcounts>` element to the suppressions that were used.
+pub fn used_suppressions_from_xml(suppcounts: &Element) -> Vec<UsedSuppression> {
+    suppcounts.children_named("pair").iter().map(|pair| {
+        UsedSuppression {
+            name: pair.child_text("name").unwrap_or(String::new()),
+            count: pair.child_text("count").and_then(|count| from_str(count.as_slice())).unwrap_or(0),
+        }
+    }).collect()
+}
+
+/// Converts the root `<valgrindoutput>` element of the XML output to a report.
+pub fn report_from_xml(root: &Element) -> Report {
+    let tool = root.child_text("tool").unwrap_or(String::new());
+    Report {
+        opt_pid: root.child_text("pid").and_then(|pid| from_str(pid.as_slice())),
+        opt_ppid: root.child_text("ppid").and_then(|ppid| from_str(ppid.as_slice())),
+        opt_tool: if tool.is_empty() { None } else { Some(tool.clone()) },
+        errors: root.children_named("error").iter().map(|error| error_from_xml(tool.as_slice(), *error)).collect(),
+        used_suppressions: match root.child("suppcounts") {
+            None => Vec::new(),
+            Some(suppcounts) => used_suppressions_from_xml(suppcounts),
+        },
+        opt_heap_summary: None,
+        opt_leak_summary: None,
+        opt_error_summary: None,
+    }
+}
+
+/// Parses the XML output of Valgrind into a report.
+pub fn parse_report(text: &str) -> Result<Report, XmlError> {
+    let root = try!(parse_document(text));
+    if root.name.as_slice() != "valgrindoutput" {
+        return Err(XmlError {
+            offset: 0,
+            message: format!("expecting a 'valgrindoutput' root element, not '{}'", root.name),
+        });
+    }
+    Ok(report_from_xml(&root))
+}
@@ -0,0 +1,466 @@
+// Copyright (C) 2014  Daniel Trebbien
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public
+// License as published by the Free Software Foundation; either
+// version 3 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Lesser General Public License for more details.
+
+//! Incremental parsing of the XML output that Valgrind streams with `--xml-socket=host:port`.
+
+use std::collections::{Deque, RingBuf};
+use std::comm::{Receiver, channel};
+use std::fmt::{FormatError, Formatter, Show};
+use std::from_str::{from_str};
+use std::io::{Acceptor, EndOfFile, IoError, IoResult, Listener};
+use std::io::net::ip::{SocketAddr};
+use std::io::net::tcp::{TcpAcceptor, TcpListener, TcpStream};
+use std::mem;
+use std::option::{Option};
+use std::result::{Result};
+use std::str;
+use std::string::{String};
+use std::task;
+use std::vec::{Vec};
+
+use report::{UsedSuppression, ValgrindError};
+use xml::{Element, ElementNode, TextNode, XmlError, EndTag, StartTag, Text, Tokenizer};
+use xml::{error_from_xml, used_suppressions_from_xml};
+
+/// The port that Valgrind connects to if `--xml-socket=` does not give one.
+pub static DEFAULT_PORT: u16 = 1500;
+
+/// An event of the XML output of a process.
+#[deriving(Clone)]
+pub enum XmlEvent {
+    /// The process ID of the process, from `<pid>`.
+    PidEvent(uint),
+    /// The process ID of the parent process, from `<ppid>`.
+    PpidEvent(uint),
+    /// The name of the tool, from `<tool>`.
+    ToolEvent(String),
+    /// The state of the process (`RUNNING` or `FINISHED`), from `<status>`.
+    StatusEvent(String),
+    /// An error, as soon as its `<error>` element is complete.
+    ErrorEvent(ValgrindError),
+    /// The suppressions that were used, from `<suppcounts>`.
+    UsedSuppressionsEvent(Vec<UsedSuppression>),
+    /// The end of the output, i.e. the end tag of the root element.
+    EndEvent,
+}
+
+/// An error generated while reading the XML output of a process.
+pub enum StreamError {
+    /// Reading from the connection failed.
+    StreamIoError(IoError),
+    /// The XML output was malformed.
+    StreamXmlError(XmlError),
+}
+
+impl Show for StreamError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FormatError> {
+        match *self {
+            StreamIoError(ref e) => write!(fmt, "{}", e),
+            StreamXmlError(ref e) => write!(fmt, "invalid XML output: {}", e),
+        }
+    }
+}
+
+/// An incremental parser of the XML output of Valgrind. Data can be fed in chunks of any size;
+/// the children of the root element are discarded once their events have been returned, so the
+/// memory used does not grow with the length of the output.
+pub struct XmlStreamParser {
+    /// Bytes that have been fed, but not yet parsed.
+    pending: Vec<u8>,
+    /// The number of bytes that have been parsed.
+    consumed: uint,
+    /// The elements that are open.
+    stack: Vec<Element>,
+    /// The name of the tool, once known.
+    tool: String,
+    finished: bool,
+}
+
+impl XmlStreamParser {
+    pub fn new() -> XmlStreamParser {
+        XmlStreamParser {
+            pending: Vec::new(),
+            consumed: 0,
+            stack: Vec::new(),
+            tool: String::new(),
+            finished: false,
+        }
+    }
+
+    /// Returns whether the end tag of the root element has been parsed.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Parses `data`, returning the events of the elements that it completes.
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<XmlEvent>, XmlError> {
+        self.pending.push_all(data);
+
+        // Only parse up to the last `>`, so that tags and character data are never split. `>` is
+        // ASCII, so this is also never within a UTF-8 sequence.
+        let end = match self.pending.iter().rposition(|&b| b == '>' as u8) {
+                None => return Ok(Vec::new()),
+                Some(pos) => pos + 1,
+            };
+        let rest = self.pending.slice_from(end).to_vec();
+        let complete = mem::replace(&mut self.pending, rest);
+        let base = self.consumed;
+        self.consumed = self.consumed + end;
+        let text = match str::from_utf8(complete.slice_to(end)) {
+                None => {
+                    return Err(XmlError {
+                        offset: base,
+                        message: String::from_str("the output is not valid UTF-8"),
+                    });
+                },
+                Some(text) => text,
+            };
+
+        let mut events = Vec::new();
+        let mut tokenizer = Tokenizer::new(text);
+        loop {
+            let pos = tokenizer.pos();
+            let token = match tokenizer.next_token() {
+                    Err(e) => {
+                        return Err(XmlError {
+                            offset: base + e.offset,
+                            message: e.message,
+                        });
+                    },
+                    Ok(None) => break,
+                    Ok(Some(token)) => token,
+                };
+            match token {
+                StartTag(name, attributes, self_closing) => {
+                    let element = Element {
+                        name: name,
+                        attributes: attributes,
+                        children: Vec::new(),
+                    };
+                    if self_closing {
+                        self.close(element, &mut events);
+                    } else {
+                        self.stack.push(element);
+                    }
+                },
+                EndTag(name) => {
+                    match self.stack.pop() {
+                        Some(element) if element.name == name => self.close(element, &mut events),
+                        _ => {
+                            return Err(XmlError {
+                                offset: base + pos,
+                                message: format!("unexpected end tag '{}'", name),
+                            });
+                        },
+                    }
+                },
+                Text(text) => {
+                    // Character data directly within the root element is only whitespace.
+                    if self.stack.len() > 1 {
+                        self.stack.mut_last().unwrap().children.push(TextNode(text));
+                    }
+                },
+            }
+        }
+        Ok(events)
+    }
+
+    /// Handles the completed element `element`.
+    fn close(&mut self, element: Element, events: &mut Vec<XmlEvent>) {
+        match self.stack.len() {
+            0 => {
+                self.finished = true;
+                events.push(EndEvent);
+            },
+            1 => {
+                match self.top_level_event(&element) {
+                    Some(event) => events.push(event),
+                    None => (),
+                }
+            },
+            _ => self.stack.mut_last().unwrap().children.push(ElementNode(element)),
+        }
+    }
+
+    /// Returns the event of a completed child of the root element, if any.
+    fn top_level_event(&mut self, element: &Element) -> Option<XmlEvent> {
+        let text = element.text();
+        let text = text.as_slice().trim();
+        match element.name.as_slice() {
+            "pid" => from_str(text).map(PidEvent),
+            "ppid" => from_str(text).map(PpidEvent),
+            "tool" => {
+                self.tool = text.to_string();
+                Some(ToolEvent(text.to_string()))
+            },
+            "status" => element.child_text("state").map(StatusEvent),
+            "error" => Some(ErrorEvent(error_from_xml(self.tool.as_slice(), element))),
+            "suppcounts" => Some(UsedSuppressionsEvent(used_suppressions_from_xml(element))),
+            _ => None,
+        }
+    }
+}
+
+/// A connection from a process run with `--xml-socket=`, which yields the events of its output
+/// as they arrive.
+pub struct XmlConnection {
+    stream: TcpStream,
+    parser: XmlStreamParser,
+    events: RingBuf<XmlEvent>,
+    opt_pid: Option<uint>,
+    done: bool,
+}
+
+impl XmlConnection {
+    pub fn new(stream: TcpStream) -> XmlConnection {
+        XmlConnection {
+            stream: stream,
+            parser: XmlStreamParser::new(),
+            events: RingBuf::new(),
+            opt_pid: None,
+            done: false,
+        }
+    }
+
+    /// The process ID of the connected process, once its `<pid>` has been received.
+    pub fn pid(&self) -> Option<uint> {
+        self.opt_pid
+    }
+}
+
+impl Iterator<Result<XmlEvent, StreamError>> for XmlConnection {
+    fn next(&mut self) -> Option<Result<XmlEvent, StreamError>> {
+        loop {
+            match self.events.pop_front() {
+                Some(event) => {
+                    match event {
+                        PidEvent(pid) => self.opt_pid = Some(pid),
+                        _ => (),
+                    }
+                    return Some(Ok(event));
+                },
+                None => (),
+            }
+            if self.done {
+                return None;
+            }
+            let mut buf = [0u8, ..4096];
+            match self.stream.read(buf) {
+                Err(ref e) if e.kind == EndOfFile => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(StreamIoError(e)));
+                },
+                Ok(n) => {
+                    match self.parser.feed(buf.slice_to(n)) {
+                        Err(e) => {
+                            self.done = true;
+                            return Some(Err(StreamXmlError(e)));
+                        },
+                        Ok(events) => self.events.extend(events.move_iter()),
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// An event of one of the connections accepted by `XmlSocketListener::spawn()`.
+pub struct ConnectionEvent {
+    /// The number of the connection, counting from 0 in the order in which they were accepted.
+    pub connection: uint,
+    /// The process ID of the connected process, if known when the event was received.
+    pub opt_pid: Option<uint>,
+    pub event: Result<XmlEvent, StreamError>,
+}
+
+/// A TCP listener for processes run with `--xml-socket=host:port`.
+pub struct XmlSocketListener {
+    acceptor: TcpAcceptor,
+}
+
+impl XmlSocketListener {
+
+    /// Listens on `addr` and `port`. A port of 0 picks any free port; see `socket_name()`.
+    pub fn bind(addr: &str, port: u16) -> IoResult<XmlSocketListener> {
+        let listener = try!(TcpListener::bind(addr, port));
+        Ok(XmlSocketListener {
+            acceptor: try!(listener.listen()),
+        })
+    }
+
+    /// The address that the listener is listening on.
+    pub fn socket_name(&mut self) -> IoResult<SocketAddr> {
+        self.acceptor.socket_name()
+    }
+
+    /// Waits for the next process to connect.
+    pub fn accept(&mut self) -> IoResult<XmlConnection> {
+        self.acceptor.accept().map(XmlConnection::new)
+    }
+
+    /// Accepts connections in a new task, reading each connection in a task of its own, and
+    /// returns a receiver of the events of all the connections.
+    pub fn spawn(self) -> Receiver<ConnectionEvent> {
+        let (tx, rx) = channel();
+        let mut acceptor = self.acceptor;
+        task::spawn(proc() {
+            let mut connection = 0u;
+            loop {
+                let stream = match acceptor.accept() {
+                        Err(_) => break,
+                        Ok(stream) => stream,
+                    };
+                let tx = tx.clone();
+                let id = connection;
+                connection = connection + 1;
+                task::spawn(proc() {
+                    let mut xml_connection = XmlConnection::new(stream);
+                    loop {
+                        let event = match xml_connection.next() {
+                                None => break,
+                                Some(event) => event,
+                            };
+                        let connection_event = ConnectionEvent {
+                            connection: id,
+                            opt_pid: xml_connection.pid(),
+                            event: event,
+                        };
+                        if tx.send_opt(connection_event).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Acceptor, EndOfFile};
+    use std::io::net::tcp::{TcpStream};
+    use std::task;
+
+    use super::{EndEvent, ErrorEvent, PidEvent, PpidEvent, StatusEvent, ToolEvent, UsedSuppressionsEvent};
+    use super::{XmlEvent, XmlSocketListener, XmlStreamParser};
+
+    /// The XML output of a Memcheck run, as Valgrind streams it. The function names contain
+    /// multi-byte UTF-8 sequences and character references, so that chunks can split them.
+    static RECORDED: &'static str = "<?xml version=\"1.0\"?>
+<valgrindoutput>
+<protocolversion>4</protocolversion>
+<protocoltool>memcheck</protocoltool>
+<pid>4242</pid>
+<ppid>4241</ppid>
+<tool>memcheck</tool>
+<status><state>RUNNING</state><time>00:00:00:00.050 </time></status>
+<error>
+  <unique>0x0</unique>
+  <tid>1</tid>
+  <kind>InvalidRead</kind>
+  <what>Invalid read of size 4</what>
+  <stack>
+    <frame><ip>0x400544</ip><obj>/tmp/prog</obj><fn>naïve_read&lt;int&gt;</fn></frame>
+    <frame><ip>0x400560</ip><obj>/tmp/prog</obj><fn>main</fn></frame>
+  </stack>
+</error>
+<status><state>FINISHED</state><time>00:00:00:01.250 </time></status>
+<error>
+  <unique>0x1</unique>
+  <tid>1</tid>
+  <kind>Leak_DefinitelyLost</kind>
+  <xwhat>
+    <text>1,024 bytes in 1 blocks are definitely lost in loss record 1 of 1</text>
+    <leakedbytes>1024</leakedbytes>
+    <leakedblocks>1</leakedblocks>
+  </xwhat>
+  <stack>
+    <frame><ip>0x4C2AB80</ip><obj>/usr/lib/valgrind/vgpreload_memcheck-amd64-linux.so</obj><fn>malloc</fn></frame>
+    <frame><ip>0x400530</ip><obj>/tmp/prog</obj><fn>main</fn></frame>
+  </stack>
+</error>
+<suppcounts>
+  <pair><count>2</count><name>libc leak</name></pair>
+</suppcounts>
+</valgrindoutput>
+";
+
+    /// Describes `event`, so that the events of different runs can be compared.
+    fn describe(event: &XmlEvent) -> String {
+        match *event {
+            PidEvent(pid) => format!("pid {:u}", pid),
+            PpidEvent(ppid) => format!("ppid {:u}", ppid),
+            ToolEvent(ref tool) => format!("tool {}", tool),
+            StatusEvent(ref state) => format!("status {}", state),
+            ErrorEvent(ref error) => {
+                let fn_names: Vec<&str> = error.stack.iter().map(|frame| frame.fn_name()).collect();
+                format!("error {} {} {} {}", error.type_, error.what, error.opt_leaked, fn_names.connect(","))
+            },
+            UsedSuppressionsEvent(ref used) => format!("used {:u}", used.len()),
+            EndEvent => String::from_str("end"),
+        }
+    }
+
+    /// Sends `RECORDED` through a local socket in chunks of `write_size` bytes, reads it in
+    /// chunks of at most `read_size` bytes and feeds each chunk to a parser.
+    fn replay(write_size: uint, read_size: uint) -> Vec<String> {
+        let mut listener = XmlSocketListener::bind("127.0.0.1", 0).unwrap();
+        let addr = listener.socket_name().unwrap();
+        task::spawn(proc() {
+            let mut stream = TcpStream::connect(format!("{}", addr.ip).as_slice(), addr.port).unwrap();
+            for chunk in RECORDED.as_bytes().chunks(write_size) {
+                stream.write(chunk).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+
+        let mut stream = listener.acceptor.accept().unwrap();
+        let mut parser = XmlStreamParser::new();
+        let mut events = Vec::new();
+        let mut buf = Vec::from_elem(read_size, 0u8);
+        loop {
+            match stream.read(buf.as_mut_slice()) {
+                Err(ref e) if e.kind == EndOfFile => break,
+                Err(e) => fail!("{}", e),
+                Ok(n) => {
+                    for event in parser.feed(buf.slice_to(n)).unwrap().iter() {
+                        events.push(describe(event));
+                    }
+                },
+            }
+        }
+        assert!(parser.is_finished());
+        events
+    }
+
+    #[test]
+    fn test_replay_in_chunks() {
+        let mut parser = XmlStreamParser::new();
+        let expected: Vec<String> = parser.feed(RECORDED.as_bytes()).unwrap().iter().map(describe).collect();
+        assert_eq!(expected, vec!(
+            "pid 4242".to_string(),
+            "ppid 4241".to_string(),
+            "tool memcheck".to_string(),
+            "status RUNNING".to_string(),
+            "error Memcheck:Addr4 Invalid read of size 4 None naïve_read<int>,main".to_string(),
+            "status FINISHED".to_string(),
+            "error Memcheck:Leak 1,024 bytes in 1 blocks are definitely lost in loss record 1 of 1 Some((1024, 1)) malloc,main".to_string(),
+            "used 1".to_string(),
+            "end".to_string()));
+
+        for &(write_size, read_size) in [(1u, 4096u), (4096, 1), (2, 3), (7, 5), (13, 64), (100, 17)].iter() {
+            assert_eq!(replay(write_size, read_size), expected);
+        }
+    }
+}